@@ -0,0 +1,234 @@
+use std::{
+    io::{
+        stdin,
+        stderr,
+        Write,
+    },
+    process::exit,
+    path::PathBuf,
+};
+use crate::ProgramState;
+
+
+/// How many rows above and below the cursor are drawn
+const VIEW_ROWS:usize=10;
+/// How many columns either side of the cursor are drawn
+const VIEW_COLUMNS:usize=40;
+/// How many items of each stack are shown
+const STACK_ITEMS:usize=6;
+/// How many characters of each item are shown
+const ITEM_LENGTH:usize=40;
+
+const CURSOR:&str="\x1b[7m";
+const BREAKPOINT:&str="\x1b[31m";
+const BORDER:&str="\x1b[36m";
+const RESET:&str="\x1b[0m";
+
+const HELP:&str="\
+Commands:
+  s, step            run the current cell and stop at the next one (also an empty line)
+  c, continue        run until a breakpoint
+  f, finish          run until the current procedure returns
+  b <column> <line>  toggle a breakpoint on the cell at that position in the file being shown
+  b <char>           toggle a breakpoint on every cell with that character
+  l, list            list the breakpoints
+  q, quit            stop the program
+  h, help            show this message";
+
+
+#[derive(Clone,Debug,PartialEq)]
+enum Breakpoint {
+    /// A single cell as `[column,row]` in the file with this full path. Libraries are run with
+    /// their own list of sources before being added to the importer's, so the path is what stays
+    /// the same.
+    At(PathBuf,[usize;2]),
+    /// Every cell with this character
+    Cell(char),
+}
+#[derive(Copy,Clone,Debug,PartialEq)]
+enum Mode {
+    Step,
+    Continue,
+    /// Run until there are fewer than this many procedures on the call stack
+    Finish(usize),
+    /// Stdin has ended, so there is no one left to ask
+    Detached,
+}
+/// Stops a program before it runs each cell, shows where the cursor is, and asks what to do next
+pub struct Debugger {
+    mode:Mode,
+    breakpoints:Vec<Breakpoint>,
+}
+impl Debugger {
+    pub fn new()->Self {
+        Debugger {
+            mode:Mode::Step,
+            breakpoints:Vec::new(),
+        }
+    }
+    /// Called before the cell under the cursor is run. Only returns once the user wants to carry on.
+    pub fn before_cell(&mut self,state:&ProgramState) {
+        let stop=match self.mode {
+            Mode::Step=>true,
+            Mode::Continue=>false,
+            Mode::Finish(depth)=>state.call_stack.len()<depth,
+            Mode::Detached=>return,
+        };
+        if !stop&&!self.is_breakpoint(state.cursors[0],state) {
+            return;
+        }
+        self.mode=Mode::Step;
+        self.draw(state);
+        loop {
+            eprint!("(fuzz) ");
+            stderr().flush().unwrap();
+            let mut line=String::new();
+            match stdin().read_line(&mut line) {
+                Ok(0)|Err(_)=>{
+                    eprintln!();
+                    self.mode=Mode::Detached;
+                    return;
+                },
+                Ok(_)=>{},
+            }
+            let mut words=line.split_whitespace();
+            match (words.next(),words.next(),words.next()) {
+                (None,..)|(Some("s"|"step"),None,None)=>return,
+                (Some("c"|"continue"),None,None)=>{
+                    self.mode=Mode::Continue;
+                    return;
+                },
+                (Some("f"|"finish"),None,None)=>{
+                    if state.call_stack.len()==0 {
+                        eprintln!("Not inside a procedure");
+                        continue;
+                    }
+                    self.mode=Mode::Finish(state.call_stack.len());
+                    return;
+                },
+                (Some("b"|"break"),Some(column),Some(row))=>{
+                    match (column.parse::<usize>(),row.parse::<usize>()) {
+                        (Ok(column),Ok(row)) if column>0&&row>0=>{
+                            self.toggle(Breakpoint::At(state.source().full_path.clone(),[column-1,row-1]));
+                        },
+                        _=>eprintln!("Expected a column and line starting from 1"),
+                    }
+                },
+                (Some("b"|"break"),Some(c),None) if c.chars().count()==1=>{
+                    self.toggle(Breakpoint::Cell(c.chars().next().unwrap()));
+                },
+                (Some("l"|"list"),None,None)=>{
+                    if self.breakpoints.is_empty() {
+                        eprintln!("No breakpoints");
+                    }
+                    for breakpoint in self.breakpoints.iter() {
+                        eprintln!("  {}",describe(breakpoint));
+                    }
+                },
+                (Some("q"|"quit"),None,None)=>exit(0),
+                (Some("h"|"help"),None,None)=>eprintln!("{}",HELP),
+                _=>eprintln!("Unknown command. Type `h` for help."),
+            }
+        }
+    }
+    fn toggle(&mut self,breakpoint:Breakpoint) {
+        if let Some(i)=self.breakpoints.iter().position(|b|*b==breakpoint) {
+            self.breakpoints.remove(i);
+            eprintln!("Removed breakpoint {}",describe(&breakpoint));
+        } else {
+            eprintln!("Added breakpoint {}",describe(&breakpoint));
+            self.breakpoints.push(breakpoint);
+        }
+    }
+    fn is_breakpoint(&self,position:[usize;2],state:&ProgramState)->bool {
+        let c=state.cells()[position[1]][position[0]].into_char();
+        self.breakpoints.iter().any(|breakpoint|match breakpoint {
+            Breakpoint::At(path,at)=>*at==position&&*path==state.source().full_path,
+            Breakpoint::Cell(b)=>*b==c,
+        })
+    }
+    /// Draw the grid around the cursor, followed by the call stack and the top of each stack
+    fn draw(&self,state:&ProgramState) {
+        let [x,y]=state.cursors[0];
+        let in_proc=state.call_stack.len()>0;
+        let border=state.borders[0];
+        let on_border=|column:usize,row:usize|{
+            in_proc&&
+                column>=border.tl[0]&&column<=border.br[0]&&
+                row>=border.tl[1]&&row<=border.br[1]&&
+                (column==border.tl[0]||column==border.br[0]||row==border.tl[1]||row==border.br[1])
+        };
+
+        // Only scroll when the grid doesn't fit, so the view stays still while the cursor moves
+        let rows=state.cells().len();
+        let columns=state.cells()[0].len();
+        let first_row=y.saturating_sub(VIEW_ROWS).min(rows.saturating_sub(VIEW_ROWS*2+1));
+        let last_row=(first_row+VIEW_ROWS*2).min(rows-1);
+        let first_column=x.saturating_sub(VIEW_COLUMNS).min(columns.saturating_sub(VIEW_COLUMNS*2+1));
+        let width=(last_row+1).to_string().len();
+        let mut out=String::new();
+        out.push('\n');
+        for row in first_row..=last_row {
+            out.push_str(&format!("{:>width$} | ",row+1,width=width));
+            let cells=state.cells()[row].iter()
+                .enumerate()
+                .take(first_column+VIEW_COLUMNS*2+1)
+                .skip(first_column);
+            for (column,cell) in cells {
+                let c=cell.into_char();
+                let style=if [column,row]==[x,y] {
+                    CURSOR
+                } else if self.is_breakpoint([column,row],state) {
+                    BREAKPOINT
+                } else if on_border(column,row) {
+                    BORDER
+                } else {
+                    ""
+                };
+                if style.is_empty() {
+                    out.push(c);
+                } else {
+                    out.push_str(style);
+                    out.push(c);
+                    out.push_str(RESET);
+                }
+            }
+            out.push('\n');
+        }
+        out.push_str(&format!("At {}:{}:{} on `{}` going {:?}\n",state.source().path.display(),y+1,x+1,state.current_cell().into_char(),state.direction));
+
+        // Each procedure's stack, innermost first. The main program's stack is always at the bottom.
+        let names=state.call_stack.iter()
+            .map(|name|format!("\"{}\"",name))
+            .chain(Some("main".to_string()));
+        for (name,stack) in names.zip(state.stacks.iter()) {
+            out.push_str(&format!("  {} stack:",name));
+            if stack.len()==0 {
+                out.push_str(" empty");
+            }
+            for item in stack.iter().take(STACK_ITEMS) {
+                let item=format!("{:?}",item);
+                if item.chars().count()>ITEM_LENGTH {
+                    out.push(' ');
+                    out.extend(item.chars().take(ITEM_LENGTH-3));
+                    out.push_str("...");
+                } else {
+                    out.push_str(&format!(" {}",item));
+                }
+            }
+            if stack.len()>STACK_ITEMS {
+                out.push_str(&format!(" ...{} more",stack.len()-STACK_ITEMS));
+            }
+            out.push('\n');
+        }
+        eprint!("{}",out);
+    }
+}
+
+
+fn describe(breakpoint:&Breakpoint)->String {
+    match breakpoint {
+        Breakpoint::At(path,[column,row])=>format!("at {}:{}:{}",path.display(),row+1,column+1),
+        Breakpoint::Cell(c)=>format!("on every `{}`",c),
+    }
+}
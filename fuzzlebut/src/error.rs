@@ -0,0 +1,105 @@
+use std::{
+    fmt::{
+        Display,
+        Formatter,
+        Result as FmtResult,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use crate::{
+    parser::{
+        Cells,
+        Cell,
+    },
+    Direction,
+};
+
+
+/// How many rows above and below the cursor are shown with an error
+const CONTEXT_ROWS:usize=2;
+/// How many columns either side of the cursor are shown with an error
+const CONTEXT_COLUMNS:usize=30;
+
+
+/// Something that went wrong while running a program. It remembers where the cursor was and what
+/// it was doing so the user can find the problem in the grid.
+#[derive(Clone,Debug)]
+pub struct RuntimeError {
+    pub message:String,
+    /// The cursor position as `[column,row]`
+    pub position:[usize;2],
+    /// The file the cursor was in
+    pub path:PathBuf,
+    pub cell:Cell,
+    pub direction:Direction,
+    /// The procedures being run, innermost first
+    pub call_stack:Vec<String>,
+    /// The rows around the cursor, already clipped to the columns that are shown
+    snippet:Vec<(usize,String)>,
+}
+impl RuntimeError {
+    pub fn new(message:String,position:[usize;2],path:&Path,cells:&Cells,direction:Direction,call_stack:Vec<String>)->Self {
+        let [x,y]=position;
+        let cell=cells.get(y)
+            .and_then(|row|row.get(x))
+            .copied()
+            .unwrap_or(Cell::Nop);
+        let first_column=first_column(x);
+        let snippet=cells.iter()
+            .enumerate()
+            .skip(y.saturating_sub(CONTEXT_ROWS))
+            .take(CONTEXT_ROWS*2+1)
+            .map(|(i,row)|{
+                let line=row.iter()
+                    .skip(first_column)
+                    .take(CONTEXT_COLUMNS*2+1)
+                    .map(|cell|cell.into_char())
+                    .collect::<String>();
+                (i,line.trim_end().to_string())
+            })
+            .collect();
+        RuntimeError {
+            message,
+            position,
+            path:path.to_path_buf(),
+            cell,
+            direction,
+            call_stack,
+            snippet,
+        }
+    }
+}
+impl Display for RuntimeError {
+    fn fmt(&self,f:&mut Formatter)->FmtResult {
+        let [x,y]=self.position;
+        writeln!(f,"Error: {}",self.message)?;
+        writeln!(f,"  at {}:{}:{} on `{}` going {:?}",self.path.display(),y+1,x+1,self.cell.into_char(),self.direction)?;
+        let width=self.snippet.last()
+            .map(|(i,_)|(i+1).to_string().len())
+            .unwrap_or(1);
+        let first_column=first_column(x);
+        let ellipsis=if first_column>0 {"..."} else {""};
+        writeln!(f,"{:width$} |","",width=width)?;
+        for (i,line) in self.snippet.iter() {
+            writeln!(f,"{:>width$} | {}{}",i+1,ellipsis,line,width=width)?;
+            if *i==y {
+                writeln!(f,"{:width$} | {:>column$}","","^",width=width,column=ellipsis.len()+x-first_column+1)?;
+            }
+        }
+        writeln!(f,"{:width$} |","",width=width)?;
+        f.write_str("Backtrace:")?;
+        for name in self.call_stack.iter() {
+            write!(f,"\n  in procedure \"{}\"",name)?;
+        }
+        f.write_str("\n  in the main program")
+    }
+}
+
+
+/// The first column shown in a snippet of the grid for a cursor at column `x`
+fn first_column(x:usize)->usize {
+    x.saturating_sub(CONTEXT_COLUMNS)
+}
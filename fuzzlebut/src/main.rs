@@ -9,10 +9,24 @@ use std::{
         stdin,
         stdout,
         Write,
+        BufRead,
+        BufReader,
     },
     env::args,
-    fs::read_to_string,
-    collections::HashMap,
+    fs::{
+        read_to_string,
+        canonicalize,
+        File,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    collections::{
+        HashMap,
+        BTreeMap,
+    },
+    process::exit,
 };
 use rand::{
     rngs::SmallRng,
@@ -24,10 +38,15 @@ use parser::{
     Cell,
 };
 use stack::Stack;
+use error::RuntimeError;
+use debugger::Debugger;
 
 
 mod stack;
 mod parser;
+mod error;
+mod debugger;
+mod tester;
 
 
 type ProgramStack=Stack<Value>;
@@ -41,9 +60,25 @@ enum Value {
     Float(f64),
     Bool(bool),
     Char(char),
-    Object(HashMap<String,Self>),
+    /// Sorted by field name, so objects always print the same way
+    Object(BTreeMap<String,Self>),
     List(Vec<Self>),
 }
+impl Value {
+    /// The name of the type for error messages
+    fn type_name(&self)->&'static str {
+        use Value::*;
+        match self {
+            String(_)=>"String",
+            Int(_)=>"Int",
+            Float(_)=>"Float",
+            Bool(_)=>"Bool",
+            Char(_)=>"Char",
+            Object(_)=>"Object",
+            List(_)=>"List",
+        }
+    }
+}
 impl Display for Value {
     fn fmt(&self,f:&mut Formatter)->FmtResult {
         use Value::*;
@@ -138,19 +173,43 @@ struct Rect {
     tl:[usize;2],
     br:[usize;2],
 }
+/// A file that has been loaded, either the program itself or a library it imported
+struct Source {
+    path:PathBuf,
+    /// `path` with links and `..` resolved, so the same file is recognised however it was reached
+    full_path:PathBuf,
+    cells:Cells,
+}
+/// A procedure defined with `P`
+#[derive(Clone,Debug)]
+struct Proc {
+    rect:Rect,
+    args:usize,
+    /// Which of the loaded sources the procedure is in
+    source:usize,
+    /// The namespace it was imported under. Procedures it calls are looked for here first.
+    namespace:Option<String>,
+}
 /// Holds vital information for a program to run
 struct ProgramState {
     stacks:Stack<ProgramStack>,
     direction:Direction,
     cursors:Stack<[usize;2]>,
-    cells:Cells,
+    sources:Vec<Source>,
+    /// Which source each running procedure is in
+    source_ids:Stack<usize>,
     borders:Stack<Rect>,
-    functions:HashMap<String,(Rect,usize)>,
+    functions:HashMap<String,Proc>,
     rng:SmallRng,
     call_stack:Stack<String>,
+    debugger:Option<Debugger>,
+    /// Where `U` reads lines from instead of stdin
+    input:Option<BufReader<File>>,
+    /// The libraries being imported that led to this one, so a library can't import itself
+    importers:Vec<PathBuf>,
 }
 impl ProgramState {
-    fn new(mut cells:Cells)->Self {
+    fn new(path:PathBuf,mut cells:Cells)->Self {
         let max=cells.iter().map(|row|row.len()).max().unwrap_or(0);
         for row in cells.iter_mut() {
             while row.len()<max {
@@ -162,39 +221,45 @@ impl ProgramState {
         let mut cursors=Stack::new();
         cursors.push([0,0]);
         let mut borders=Stack::new();
-        borders.push(Rect{tl:[0,0],br:[max-1,cells.len()-1]});
+        borders.push(Rect{tl:[0,0],br:[max.saturating_sub(1),cells.len().saturating_sub(1)]});
+        let mut source_ids=Stack::new();
+        source_ids.push(0);
         ProgramState {
             borders,
             stacks,
             cursors,
-            cells,
+            sources:vec![Source{full_path:canonicalize(&path).unwrap_or_else(|_|path.clone()),path,cells}],
+            source_ids,
             direction:Direction::Right,
             functions:HashMap::new(),
             rng:SmallRng::from_entropy(),
             call_stack:Stack::new(),
+            debugger:None,
+            input:None,
+            importers:Vec::new(),
         }
     }
-    fn next_cell(&mut self) {
+    fn next_cell(&mut self)->Result<(),Box<RuntimeError>> {
         use Direction::*;
         match self.direction {
             Up=>{
                 if self.cursors[0][1]==self.borders[0].tl[1] {
-                    panic!("Error: hit the up edge at {:?}",self.cursors[0]);
+                    return Err(self.error("hit the up edge"));
                 }
             },
             Down=>{
                 if self.cursors[0][1]==self.borders[0].br[1] {
-                    panic!("Error: hit the down edge at {:?}",self.cursors[0]);
+                    return Err(self.error("hit the down edge"));
                 }
             },
             Left=>{
                 if self.cursors[0][0]==self.borders[0].tl[0] {
-                    panic!("Error: hit the left edge at {:?}",self.cursors[0]);
+                    return Err(self.error("hit the left edge"));
                 }
             },
             Right=>{
                 if self.cursors[0][0]==self.borders[0].br[0] {
-                    panic!("Error: hit the right edge at {:?}",self.cursors[0]);
+                    return Err(self.error("hit the right edge"));
                 }
             },
         }
@@ -204,28 +269,29 @@ impl ProgramState {
             Left=>self.cursors[0][0]-=1,
             Right=>self.cursors[0][0]+=1,
         }
+        Ok(())
     }
-    fn prev_cell(&mut self) {
+    fn prev_cell(&mut self)->Result<(),Box<RuntimeError>> {
         use Direction::*;
         match self.direction {
             Up=>{
                 if self.cursors[0][1]==self.borders[0].br[1] {
-                    panic!("Error: hit an edge at {:?}",self.cursors[0]);
+                    return Err(self.error("hit an edge while stepping back"));
                 }
             },
             Down=>{
                 if self.cursors[0][1]==self.borders[0].tl[1] {
-                    panic!("Error: hit an edge at {:?}",self.cursors[0]);
+                    return Err(self.error("hit an edge while stepping back"));
                 }
             },
             Left=>{
                 if self.cursors[0][0]==self.borders[0].br[0] {
-                    panic!("Error: hit an edge at {:?}",self.cursors[0]);
+                    return Err(self.error("hit an edge while stepping back"));
                 }
             },
             Right=>{
                 if self.cursors[0][0]==self.borders[0].tl[0] {
-                    panic!("Error: hit an edge at {:?}",self.cursors[0]);
+                    return Err(self.error("hit an edge while stepping back"));
                 }
             },
         }
@@ -235,16 +301,103 @@ impl ProgramState {
             Left=>self.cursors[0][0]+=1,
             Right=>self.cursors[0][0]-=1,
         }
+        Ok(())
     }
     fn current_cell(&self)->&Cell {
-        &self.cells[self.cursors[0][1]][self.cursors[0][0]]
+        &self.cells()[self.cursors[0][1]][self.cursors[0][0]]
+    }
+    /// The source the cursor is in
+    fn source(&self)->&Source {
+        &self.sources[self.source_ids[0]]
+    }
+    /// The grid the cursor is in
+    fn cells(&self)->&Cells {
+        &self.source().cells
+    }
+    /// The namespace of the running procedure, if it was imported
+    fn namespace(&self)->Option<&str> {
+        self.call_stack.get(0)
+            .and_then(|name|self.functions.get(name))
+            .and_then(|proc|proc.namespace.as_deref())
+    }
+    /// Put a name in the namespace of the running procedure
+    fn qualify(&self,name:String)->String {
+        match self.namespace() {
+            Some(namespace)=>format!("{}.{}",namespace,name),
+            None=>name,
+        }
+    }
+    /// Create an error at the cursor
+    fn error(&self,message:impl Into<String>)->Box<RuntimeError> {
+        Box::new(RuntimeError::new(
+            message.into(),
+            self.cursors[0],
+            &self.source().path,
+            self.cells(),
+            self.direction,
+            self.call_stack.iter().cloned().collect(),
+        ))
+    }
+    /// Run the file at `path`, relative to the file the cursor is in, and add the procedures it
+    /// defines under `namespace`
+    fn import(&mut self,path:&str,namespace:String)->Result<(),Box<RuntimeError>> {
+        let path=self.source().path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(path);
+        let contents=read_to_string(&path)
+            .map_err(|e|self.error(format!("could not import `{}`: {}",path.display(),e)))?;
+        let full_path=canonicalize(&path).unwrap_or_else(|_|path.clone());
+        let own_path=self.sources[0].full_path.clone();
+        if full_path==own_path||self.importers.contains(&full_path) {
+            return Err(self.error(format!("`{}` is already being imported",path.display())));
+        }
+
+        let mut library=ProgramState::new(path,parser::from_source(&contents));
+        library.importers=self.importers.clone();
+        library.importers.push(own_path);
+        library.rng=SmallRng::seed_from_u64(self.rng.gen());
+        library.input=self.input.take();
+        library.debugger=self.debugger.take();
+        let result=library.run();
+        self.input=library.input.take();
+        self.debugger=library.debugger.take();
+        result?;
+
+        let namespace=self.qualify(namespace);
+        let offset=self.sources.len();
+        self.sources.extend(library.sources);
+        for (name,mut proc) in library.functions {
+            proc.source+=offset;
+            proc.namespace=Some(match proc.namespace {
+                Some(inner)=>format!("{}.{}",namespace,inner),
+                None=>namespace.clone(),
+            });
+            self.functions.insert(format!("{}.{}",namespace,name),proc);
+        }
+        Ok(())
+    }
+    /// Pop the top item off the current stack
+    fn pop(&mut self)->Result<Value,Box<RuntimeError>> {
+        self.stacks[0].pop().ok_or_else(||self.error("the stack is empty"))
+    }
+    /// The top item of the current stack
+    fn top(&self)->Result<&Value,Box<RuntimeError>> {
+        self.stacks[0].get(0).ok_or_else(||self.error("the stack is empty"))
+    }
+    /// The top item of the current stack
+    fn top_mut(&mut self)->Result<&mut Value,Box<RuntimeError>> {
+        if self.stacks[0].len()==0 {
+            return Err(self.error("the stack is empty"));
+        }
+        Ok(&mut self.stacks[0][0])
     }
     /// Evaluate the program
-    fn run(&mut self) {
-        if self.cells.len()==0 {
-            return;
-        } else if self.cells[0].len()==0 {
-            return;
+    fn run(&mut self)->Result<(),Box<RuntimeError>> {
+        if self.cells().len()==0 {
+            return Ok(());
+        } else if self.cells()[0].len()==0 {
+            return Ok(());
         }
         use Cell::*;
         use Direction::*;
@@ -255,15 +408,19 @@ impl ProgramState {
                 // return;
             }
             iters+=1;
+            if let Some(mut debugger)=self.debugger.take() {
+                debugger.before_cell(self);
+                self.debugger=Some(debugger);
+            }
             match self.current_cell() {
                 DoubleQuote=>{
                     let mut string=String::new();
                     loop {
-                        self.next_cell();
+                        self.next_cell()?;
                         match self.current_cell() {
                             DoubleQuote=>break,
                             Other('\\')=>{
-                                self.next_cell();
+                                self.next_cell()?;
                                 match self.current_cell() {
                                     DoubleQuote=>string.push('"'),
                                     Other('n')=>string.push('\n'),
@@ -271,7 +428,7 @@ impl ProgramState {
                                     Other('t')=>string.push('\t'),
                                     Other('\\')=>string.push('\\'),
                                     Number('0')=>string.push('\0'),
-                                    c=>panic!("Invalid escape sequence `{}` at {:?}",c.into_char(),self.cursors[0]),
+                                    c=>return Err(self.error(format!("invalid escape sequence `\\{}`",c.into_char()))),
                                 }
                             },
                             c=>string.push(c.into_char()),
@@ -283,31 +440,35 @@ impl ProgramState {
                     let mut number=String::from(*n);
                     let mut float=false;
                     loop {
-                        self.next_cell();
+                        self.next_cell()?;
                         match self.current_cell() {
                             Dot=>{
                                 if float {
-                                    panic!("There can only be one decimal for each float. Location: {:?}",self.cursors[0]);
+                                    return Err(self.error("there can only be one decimal for each float"));
                                 }
                                 float=true;
                                 number.push('.');
                             },
                             Number(n)=>number.push(*n),
                             _=>{
-                                self.prev_cell();
+                                self.prev_cell()?;
                                 break;
                             },
                         }
                     }
-                    if float {
-                        self.stacks[0].push(Value::Float(number.parse().unwrap()));
+                    let value=if float {
+                        number.parse().map(Value::Float).ok()
                     } else {
-                        self.stacks[0].push(Value::Int(number.parse().unwrap()));
+                        number.parse().map(Value::Int).ok()
+                    };
+                    match value {
+                        Some(value)=>self.stacks[0].push(value),
+                        None=>return Err(self.error(format!("`{}` is not a valid number",number))),
                     }
                 },
                 Add=>{
-                    let left=self.stacks[0].pop().unwrap();
-                    let right=self.stacks[0].pop().unwrap();
+                    let left=self.pop()?;
+                    let right=self.pop()?;
                     match (left,right) {
                         (Value::List(mut items),right)=>{
                             items.push(right);
@@ -317,21 +478,23 @@ impl ProgramState {
                             write!(s,"{}",right).unwrap();
                             self.stacks[0].push(Value::String(s));
                         },
-                        (Value::Int(mut i1),Value::Int(i2))=>{
-                            i1+=i2;
-                            self.stacks[0].push(Value::Int(i1));
+                        (Value::Int(i1),Value::Int(i2))=>{
+                            match i1.checked_add(i2) {
+                                Some(i)=>self.stacks[0].push(Value::Int(i)),
+                                None=>return Err(self.error("integer overflow")),
+                            }
                         },
                         (Value::Float(mut f1),Value::Float(f2))=>{
                             f1+=f2;
                             self.stacks[0].push(Value::Float(f1));
                         },
                         (Value::Bool(b1),Value::Bool(b2))=>self.stacks[0].push(Value::Bool(b1||b2)),
-                        _=>panic!("Invalid types in add at {:?}",self.cursors[0]),
+                        (left,right)=>return Err(self.error(format!("can't add {} and {}",left.type_name(),right.type_name()))),
                     }
                 },
                 Sub=>{
-                    let left=self.stacks[0].pop().unwrap();
-                    let right=self.stacks[0].pop().unwrap();
+                    let left=self.pop()?;
+                    let right=self.pop()?;
                     match (left,right) {
                         (Value::String(mut s),Value::Int(mut len))=>{
                             while s.len()>0&&len>0 {
@@ -340,9 +503,11 @@ impl ProgramState {
                             }
                             self.stacks[0].push(Value::String(s));
                         },
-                        (Value::Int(mut i1),Value::Int(i2))=>{
-                            i1-=i2;
-                            self.stacks[0].push(Value::Int(i1));
+                        (Value::Int(i1),Value::Int(i2))=>{
+                            match i1.checked_sub(i2) {
+                                Some(i)=>self.stacks[0].push(Value::Int(i)),
+                                None=>return Err(self.error("integer overflow")),
+                            }
                         },
                         (Value::Float(mut f1),Value::Float(f2))=>{
                             f1-=f2;
@@ -359,34 +524,40 @@ impl ProgramState {
                             fields.remove(&s);
                             self.stacks[0].push(Value::Object(fields));
                         },
-                        _=>panic!("Invalid types in sub at {:?}",self.cursors[0]),
+                        (left,right)=>return Err(self.error(format!("can't subtract {} from {}",right.type_name(),left.type_name()))),
                     }
                 },
                 Mul=>{
-                    let left=self.stacks[0].pop().unwrap();
-                    let right=self.stacks[0].pop().unwrap();
+                    let left=self.pop()?;
+                    let right=self.pop()?;
                     match (left,right) {
-                        (Value::Int(mut i1),Value::Int(i2))=>{
-                            i1*=i2;
-                            self.stacks[0].push(Value::Int(i1));
+                        (Value::Int(i1),Value::Int(i2))=>{
+                            match i1.checked_mul(i2) {
+                                Some(i)=>self.stacks[0].push(Value::Int(i)),
+                                None=>return Err(self.error("integer overflow")),
+                            }
                         },
                         (Value::Float(mut f1),Value::Float(f2))=>{
                             f1*=f2;
                             self.stacks[0].push(Value::Float(f1));
                         },
                         (Value::Bool(b1),Value::Bool(b2))=>self.stacks[0].push(Value::Bool(b1&&b2)),
-                        _=>panic!("Invalid types in mul at {:?}",self.cursors[0]),
+                        (left,right)=>return Err(self.error(format!("can't multiply {} by {}",left.type_name(),right.type_name()))),
                     }
                 },
                 Div=>{
-                    let left=self.stacks[0].pop().unwrap();
-                    let right=self.stacks[0].pop().unwrap();
+                    let left=self.pop()?;
+                    let right=self.pop()?;
                     match (left,right) {
+                        (Value::Int(_),Value::Int(0))=>return Err(self.error("can't divide by zero")),
                         (Value::Int(i1),Value::Int(i2))=>{
-                            let res=i1/i2;
-                            let rem=i1%i2;
-                            self.stacks[0].push(Value::Int(res));
-                            self.stacks[0].push(Value::Int(rem));
+                            match (i1.checked_div(i2),i1.checked_rem(i2)) {
+                                (Some(res),Some(rem))=>{
+                                    self.stacks[0].push(Value::Int(res));
+                                    self.stacks[0].push(Value::Int(rem));
+                                },
+                                _=>return Err(self.error("integer overflow")),
+                            }
                         },
                         (Value::Float(f1),Value::Float(f2))=>{
                             let res=f1/f2;
@@ -394,20 +565,20 @@ impl ProgramState {
                             self.stacks[0].push(Value::Float(res));
                             self.stacks[0].push(Value::Float(rem));
                         },
-                        _=>panic!("Invalid types in div at {:?}",self.cursors[0]),
+                        (left,right)=>return Err(self.error(format!("can't divide {} by {}",left.type_name(),right.type_name()))),
                     }
                 },
-                Object=>self.stacks[0].push(Value::Object(HashMap::new())),
+                Object=>self.stacks[0].push(Value::Object(BTreeMap::new())),
                 Field=>{
-                    let object=self.stacks[0].pop().unwrap();
-                    let name=self.stacks[0].pop().unwrap();
-                    let data=self.stacks[0].pop().unwrap();
+                    let object=self.pop()?;
+                    let name=self.pop()?;
+                    let data=self.pop()?;
                     match (object,name) {
                         (Value::Object(mut o),Value::String(name))=>{
                             o.insert(name,data);
                             self.stacks[0].push(Value::Object(o));
                         },
-                        _=>panic!("Invalid types for field create at {:?}",self.cursors[0]),
+                        (object,name)=>return Err(self.error(format!("can't add a field to {} with a {} name",object.type_name(),name.type_name()))),
                     }
                 },
                 SetUp=>self.direction=Up,
@@ -416,20 +587,23 @@ impl ProgramState {
                 SetRight=>self.direction=Right,
                 WireVert=>{
                     match self.direction {
-                        Left|Right=>panic!("Hit a vertical wire while going {:?} at {:?}. Please use direction changes.",self.direction,self.cursors[0]),
+                        Left|Right=>return Err(self.error("hit a vertical wire sideways. Please use direction changes.")),
                         _=>{},
                     }
                 },
                 WireHoriz=>{
                     match self.direction {
-                        Up|Down=>panic!("Hit a horizontal wire while going {:?} at {:?}. Please use direction changes.",self.direction,self.cursors[0]),
+                        Up|Down=>return Err(self.error("hit a horizontal wire head on. Please use direction changes.")),
                         _=>{},
                     }
                 },
                 WireCross=>{},
                 Negate=>{
-                    match &mut self.stacks[0][0] {
-                        Value::Int(i)=>*i*=-1,
+                    match self.top_mut()? {
+                        Value::Int(i)=>match i.checked_neg() {
+                            Some(negated)=>*i=negated,
+                            None=>return Err(self.error("integer overflow")),
+                        },
                         Value::Float(f)=>*f*=-1.0,
                         Value::Bool(b)=>*b=!*b,
                         _=>{},
@@ -437,36 +611,36 @@ impl ProgramState {
                 },
                 Delete=>{self.stacks[0].pop();},
                 NumberCast=>{
-                    match self.stacks[0].pop().unwrap() {
+                    match self.pop()? {
                         Value::Float(f)=>self.stacks[0].push(Value::Int(f as i64)),
                         Value::Int(i)=>self.stacks[0].push(Value::Float(i as f64)),
                         i=>self.stacks[0].push(i),
                     }
                 },
                 Print=>{
-                    print!("{}",self.stacks[0][0]);
+                    print!("{}",self.top()?);
                     stdout().flush().unwrap();
                 },
                 Println=>{
-                    println!("{}",self.stacks[0][0]);
+                    println!("{}",self.top()?);
                 },
                 ProcDef=>{
-                    let name=match self.stacks[0].pop().unwrap() {
+                    let name=match self.pop()? {
                         Value::String(s)=>s,
-                        _=>panic!("Invalid type for procedure arg count at {:?}. Expected String",self.cursors[0]),
+                        v=>return Err(self.error(format!("expected a String for the procedure name, but got {}",v.type_name()))),
                     };
-                    let arg_count=match self.stacks[0].pop().unwrap() {
+                    let arg_count=match self.pop()? {
                         Value::Int(i)=>i,
-                        _=>panic!("Invalid type for procedure arg count at {:?}. Expected Int",self.cursors[0]),
+                        v=>return Err(self.error(format!("expected an Int for the procedure argument count, but got {}",v.type_name()))),
                     };
                     if arg_count<0 {
-                        panic!("Procedure argument count should be above 0");
+                        return Err(self.error("procedure argument count should be above 0"));
                     }
                     let tl=self.cursors[0];
                     let mut br=self.cursors[0];
                     self.direction=Right;
                     loop {
-                        self.next_cell();
+                        self.next_cell()?;
                         br[0]+=1;
                         match self.current_cell() {
                             WireVert=>{
@@ -477,7 +651,7 @@ impl ProgramState {
                         }
                     }
                     loop {
-                        self.next_cell();
+                        self.next_cell()?;
                         br[1]+=1;
                         match self.current_cell() {
                             WireHoriz=>{
@@ -490,25 +664,30 @@ impl ProgramState {
                     self.direction=Left;
                     for _ in tl[0]..br[0] {
                         if self.current_cell()!=&WireHoriz {
-                            panic!("Invalid cell {:?} at {:?}. Expected WireHoriz",self.current_cell(),self.cursors[0]);
+                            return Err(self.error("expected `-` along the bottom of the procedure"));
                         }
-                        self.next_cell();
+                        self.next_cell()?;
                     }
                     self.direction=Up;
                     for _ in tl[1]..(br[1]-1) {
                         if self.current_cell()!=&WireVert {
-                            panic!("Invalid cell {:?} at {:?}. Expected WireVert",self.current_cell(),self.cursors[0]);
+                            return Err(self.error("expected `|` along the left of the procedure"));
                         }
-                        self.next_cell();
+                        self.next_cell()?;
                     }
                     self.cursors[0]=br;
-                    let rect=Rect{tl,br};
-                    self.functions.insert(name,(rect,arg_count as usize));
+                    let proc=Proc {
+                        rect:Rect{tl,br},
+                        args:arg_count as usize,
+                        source:self.source_ids[0],
+                        namespace:self.namespace().map(String::from),
+                    };
+                    self.functions.insert(self.qualify(name),proc);
                     self.direction=Right;
                 },
                 Greater=>{
-                    let left=self.stacks[0].pop().unwrap();
-                    let right=self.stacks[0].pop().unwrap();
+                    let left=self.pop()?;
+                    let right=self.pop()?;
                     match (left,right) {
                         (Value::Float(f1),Value::Float(f2))=>self.stacks[0].push(Value::Bool(f1>f2)),
                         (Value::Int(i1),Value::Int(i2))=>self.stacks[0].push(Value::Bool(i1>i2)),
@@ -516,8 +695,8 @@ impl ProgramState {
                     }
                 },
                 Less=>{
-                    let left=self.stacks[0].pop().unwrap();
-                    let right=self.stacks[0].pop().unwrap();
+                    let left=self.pop()?;
+                    let right=self.pop()?;
                     match (left,right) {
                         (Value::Float(f1),Value::Float(f2))=>self.stacks[0].push(Value::Bool(f1<f2)),
                         (Value::Int(i1),Value::Int(i2))=>self.stacks[0].push(Value::Bool(i1<i2)),
@@ -525,8 +704,8 @@ impl ProgramState {
                     }
                 },
                 Equality=>{
-                    let left=self.stacks[0].pop().unwrap();
-                    let right=self.stacks[0].pop().unwrap();
+                    let left=self.pop()?;
+                    let right=self.pop()?;
                     self.stacks[0].push(Value::Bool(left==right));
                 },
                 True=>self.stacks[0].push(Value::Bool(true)),
@@ -542,19 +721,27 @@ impl ProgramState {
                 Rotate=>self.stacks[0].rotate(),
                 RotateRev=>self.stacks[0].rotate_rev(),
                 RunProc=>{
-                    let name=match self.stacks[0].pop().unwrap() {
+                    let name=match self.pop()? {
                         Value::String(s)=>s,
-                        _=>panic!("Invalid type for procedure arg count at {:?}. Expected String",self.cursors[0]),
+                        v=>return Err(self.error(format!("expected a String for the procedure name, but got {}",v.type_name()))),
                     };
-                    if let Some((boundary,args))=self.functions.get(&name) {
-                        self.borders.push(*boundary);
+                    // Procedures from the same library are found without their namespace
+                    let local=self.qualify(name.clone());
+                    let name=if self.functions.contains_key(&local) {local} else {name};
+                    if let Some(proc)=self.functions.get(&name) {
+                        let (boundary,args,source)=(proc.rect,proc.args,proc.source);
+                        if self.stacks[0].len()<args {
+                            return Err(self.error(format!("procedure \"{}\" takes {} arguments, but the stack only has {}",name,args,self.stacks[0].len())));
+                        }
+                        self.borders.push(boundary);
                         let mut cursor=boundary.tl.clone();
                         cursor[0]+=1;
                         cursor[1]+=1;
                         self.cursors.push(cursor);
+                        self.source_ids.push(source);
                         self.stacks.push(ProgramStack::new());
-                        let mut items=vec![Value::Bool(false);*args];
-                        for i in (0..*args).rev() {
+                        let mut items=vec![Value::Bool(false);args];
+                        for i in (0..args).rev() {
                             let arg=self.stacks[1].pop().unwrap();
                             items[i]=arg;
                         }
@@ -564,7 +751,7 @@ impl ProgramState {
                         let ret_dir=self.direction;
                         // println!("Call {}",name);
                         self.call_stack.push(name);
-                        self.run();
+                        self.run()?;
                         self.call_stack.pop();
                         self.direction=ret_dir;
                         self.borders.pop();
@@ -572,15 +759,34 @@ impl ProgramState {
                             self.stacks[1].push(item);
                         }
                         self.cursors.pop();
+                        self.source_ids.pop();
                         self.stacks.pop();
                     } else {
-                        panic!("Function {} not found at {:?}",name,self.cursors[0]);
+                        return Err(self.error(format!("there is no procedure named \"{}\"",name)));
                     }
                 },
+                Import=>{
+                    let namespace=match self.pop()? {
+                        Value::String(s)=>s,
+                        v=>return Err(self.error(format!("expected a String for the import namespace, but got {}",v.type_name()))),
+                    };
+                    let path=match self.pop()? {
+                        Value::String(s)=>s,
+                        v=>return Err(self.error(format!("expected a String for the import path, but got {}",v.type_name()))),
+                    };
+                    self.import(&path,namespace)?;
+                },
                 UserInput=>{
-                    let stdin=stdin();
                     let mut s=String::new();
-                    stdin.read_line(&mut s).expect("Could not read STDIN");
+                    let read=match &mut self.input {
+                        Some(input)=>input.read_line(&mut s),
+                        None=>stdin().read_line(&mut s),
+                    };
+                    match read {
+                        Ok(0)=>return Err(self.error("there is no more input to read")),
+                        Ok(_)=>{},
+                        Err(e)=>return Err(self.error(format!("could not read the input: {}",e))),
+                    }
                     while let Some(c)=s.pop() {
                         match c {
                             '\r'|'\n'=>{},  // support Windows, Unix, and MacOS linefeeds. Also just get rid of unwanted characters at the end of the user input.
@@ -593,23 +799,23 @@ impl ProgramState {
                     self.stacks[0].push(Value::String(s));
                 },
                 Swap=>{
-                    let first=self.stacks[0].pop().unwrap();
-                    let second=self.stacks[0].pop().unwrap();
+                    let first=self.pop()?;
+                    let second=self.pop()?;
                     self.stacks[0].push(first);
                     self.stacks[0].push(second);
                 },
                 Nop|Other(_)=>{},
                 Duplicate=>{
-                    let dup=self.stacks[0][0].clone();
+                    let dup=self.top()?.clone();
                     self.stacks[0].push(dup);
                 },
                 Char=>{
-                    self.next_cell();
+                    self.next_cell()?;
                     let c=self.current_cell().into_char();
                     self.stacks[0].push(Value::Char(c));
                 },
                 Dot=>{  // String Split
-                    match &self.stacks[0][0] {
+                    match self.top()? {
                         Value::String(s)=>{
                             let val=Value::List(s.chars().map(Value::Char).collect());
                             self.stacks[0].push(val);
@@ -618,20 +824,26 @@ impl ProgramState {
                     }
                 },
                 Pop=>{
-                    match &mut self.stacks[0][0] {
+                    match self.top_mut()? {
                         Value::String(s)=>{
-                            let c=s.pop().unwrap();
+                            let c=match s.pop() {
+                                Some(c)=>c,
+                                None=>return Err(self.error("can't pop from an empty String")),
+                            };
                             self.stacks[0].push(Value::Char(c));
                         },
                         Value::List(l)=>{
-                            let item=l.pop().unwrap();
+                            let item=match l.pop() {
+                                Some(item)=>item,
+                                None=>return Err(self.error("can't pop from an empty List")),
+                            };
                             self.stacks[0].push(item);
                         },
                         _=>{},
                     }
                 },
                 Branch=>{
-                    match self.stacks[0].pop().unwrap() {
+                    match self.pop()? {
                         Value::Bool(true)=>{
                             self.direction.turn_ccw();
                         },
@@ -639,7 +851,7 @@ impl ProgramState {
                     }
                 },
                 BranchRev=>{
-                    match self.stacks[0].pop().unwrap() {
+                    match self.pop()? {
                         Value::Bool(true)=>{
                             self.direction.turn_cw();
                         },
@@ -647,7 +859,7 @@ impl ProgramState {
                     }
                 },
                 Length=>{
-                    match &self.stacks[0][0] {
+                    match self.top()? {
                         Value::String(s)=>{
                             let len=s.chars().count();
                             self.stacks[0].push(Value::Int(len as i64));
@@ -672,14 +884,17 @@ impl ProgramState {
                     self.stacks[0].debug_print();
                 },
                 RandInt=>{
-                    let max=self.stacks[0].pop().unwrap();
-                    let min=self.stacks[0].pop().unwrap();
+                    let max=self.pop()?;
+                    let min=self.pop()?;
                     match (min,max) {
                         (Value::Int(0),Value::Int(0))=>{
                             let random=self.rng.gen();
                             self.stacks[0].push(Value::Int(random));
                         },
                         (Value::Int(min),Value::Int(max))=>{
+                            if min>max {
+                                return Err(self.error(format!("can't pick a random number between {} and {}",min,max)));
+                            }
                             let random=self.rng.gen_range(min..=max);
                             self.stacks[0].push(Value::Int(random));
                         },
@@ -692,14 +907,17 @@ impl ProgramState {
                     }
                 },
                 RandFloat=>{
-                    let max=self.stacks[0].pop().unwrap();
-                    let min=self.stacks[0].pop().unwrap();
+                    let max=self.pop()?;
+                    let min=self.pop()?;
                     match (min,max) {
                         (Value::Float(min),Value::Float(max))=>{
                             if min==max&&min==0.0 {
                                 let random=self.rng.gen();
                                 self.stacks[0].push(Value::Float(random));
                             } else {
+                                if min>max||!min.is_finite()||!max.is_finite() {
+                                    return Err(self.error(format!("can't pick a random number between {} and {}",min,max)));
+                                }
                                 let random=self.rng.gen_range(min..=max);
                                 self.stacks[0].push(Value::Float(random));
                             }
@@ -713,47 +931,125 @@ impl ProgramState {
                     }
                 },
                 ListRotateLeft=>{
-                    match &mut self.stacks[0][0] {
+                    match self.top_mut()? {
                         Value::List(list)=>list.rotate_left(1),
                         _=>{},
                     }
                 },
                 ListRotateRight=>{
-                    match &mut self.stacks[0][0] {
+                    match self.top_mut()? {
                         Value::List(list)=>list.rotate_right(1),
                         _=>{},
                     }
                 },
                 CharCast=>{
-                    match self.stacks[0].pop().unwrap() {
+                    match self.pop()? {
                         Value::Char(c)=>{
                             self.stacks[0].push(Value::Int(c as u32 as i64));
                         },
                         Value::Int(i)=>{
-                            if i>(char::MAX as i64) {
-                                panic!("Number `{}` out of range for char cast {:?}",i,self.cursors[0]);
+                            if i<0||i>(char::MAX as i64) {
+                                return Err(self.error(format!("number `{}` is out of range for a char cast",i)));
                             }
                             match char::from_u32(i as u32) {
                                 Some(c)=>self.stacks[0].push(Value::Char(c)),
-                                None=>panic!("Number {} is not a valid char {:?}",i,self.cursors[0]),
+                                None=>return Err(self.error(format!("number `{}` is not a valid char",i))),
                             }
                         },
                         i=>self.stacks[0].push(i),
                     }
                 },
             }
-            self.next_cell();
+            self.next_cell()?;
         }
+        Ok(())
     }
 }
 
 
+const USAGE:&str="\
+Usage:
+  interpreter [--debug] [--seed <number>] [--input <file>] <file.fuzz>...
+  interpreter test [--update] [directory]";
+
+
 fn main() {
-    for filename in args().skip(1) {
+    let mut args=args().skip(1).peekable();
+    if args.peek().map(String::as_str)==Some("test") {
+        args.next();
+        let mut update=false;
+        let mut directory=None;
+        for arg in args {
+            match arg.as_str() {
+                "--update"=>update=true,
+                _ if directory.is_none()=>directory=Some(arg),
+                _=>{
+                    eprintln!("{}",USAGE);
+                    exit(1);
+                },
+            }
+        }
+        let passed=tester::run(directory.as_deref().unwrap_or("examples"),update);
+        exit(if passed {0} else {1});
+    }
+
+    let mut debug=false;
+    let mut seed=None;
+    let mut input=None;
+    let mut filenames=Vec::new();
+    while let Some(arg)=args.next() {
+        match arg.as_str() {
+            "--debug"=>debug=true,
+            "--seed"=>match args.next().and_then(|seed|seed.parse::<u64>().ok()) {
+                Some(number)=>seed=Some(number),
+                None=>{
+                    eprintln!("Error: `--seed` needs a whole number\n{}",USAGE);
+                    exit(1);
+                },
+            },
+            "--input"=>match args.next() {
+                Some(path)=>input=Some(path),
+                None=>{
+                    eprintln!("Error: `--input` needs a file\n{}",USAGE);
+                    exit(1);
+                },
+            },
+            _=>filenames.push(arg),
+        }
+    }
+    if filenames.is_empty() {
+        eprintln!("{}",USAGE);
+        exit(1);
+    }
+    for filename in filenames {
         // Run every file passed to it
-        let contents=read_to_string(filename).unwrap();
+        let contents=match read_to_string(&filename) {
+            Ok(contents)=>contents,
+            Err(e)=>{
+                eprintln!("Error: could not read `{}`: {}",filename,e);
+                exit(1);
+            },
+        };
         let cells=parser::from_source(&contents);
-        let mut program=ProgramState::new(cells);
-        program.run();
+        let mut program=ProgramState::new(PathBuf::from(&filename),cells);
+        if debug {
+            program.debugger=Some(Debugger::new());
+        }
+        if let Some(seed)=seed {
+            program.rng=SmallRng::seed_from_u64(seed);
+        }
+        if let Some(path)=&input {
+            match File::open(path) {
+                Ok(file)=>program.input=Some(BufReader::new(file)),
+                Err(e)=>{
+                    eprintln!("Error: could not read `{}`: {}",path,e);
+                    exit(1);
+                },
+            }
+        }
+        if let Err(e)=program.run() {
+            eprintln!("{}",e);
+            exit(1);
+        }
     }
 }
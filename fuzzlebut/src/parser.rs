@@ -49,6 +49,7 @@ pub enum Cell {
     ListRotateLeft,
     ListRotateRight,
     CharCast,
+    Import,
     Number(char),
     Other(char),
 }
@@ -101,6 +102,7 @@ impl Cell {
             ListRotateLeft=>'[',
             ListRotateRight=>']',
             CharCast=>'$',
+            Import=>'I',
             Number(n)=>n,
             Other(c)=>c,
         }
@@ -153,6 +155,7 @@ impl Cell {
             '['=>ListRotateLeft,
             ']'=>ListRotateRight,
             '$'=>CharCast,
+            'I'=>Import,
             '0'..='9'=>Number(c),
             _=>Other(c),
         }
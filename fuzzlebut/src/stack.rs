@@ -38,6 +38,15 @@ impl<T> Stack<T> {
     }
     #[inline]
     pub fn len(&self)->usize {self.items.len()}
+    /// Like indexing, but returns `None` instead of panicking when the stack isn't deep enough
+    pub fn get(&self,index:usize)->Option<&T> {
+        let last=self.items.len().checked_sub(1)?;
+        self.items.get(last.checked_sub(index)?)
+    }
+    /// Iterate through the items from top to bottom
+    pub fn iter(&self)->impl Iterator<Item=&T> {
+        self.items.iter().rev()
+    }
 }
 impl<T:Debug> Stack<T> {
     /// Iterate through the items on the stack and print them out from top to bottom where top is
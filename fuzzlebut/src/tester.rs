@@ -0,0 +1,149 @@
+use std::{
+    env::current_exe,
+    fs::{
+        read_dir,
+        read_to_string,
+        write,
+    },
+    path::Path,
+    process::{
+        Command,
+        Stdio,
+    },
+};
+
+
+/// The seed every program is tested with, so random numbers come out the same each time
+const SEED:&str="0";
+/// How many unchanged lines are shown around each change
+const CONTEXT_LINES:usize=2;
+
+
+/// Run every `.fuzz` file in `directory` and compare what it prints with the `.out` file next to it.
+/// If there's a `.in` file, it is used as the input. With `update`, the `.out` files are written
+/// instead of compared. Returns whether every program printed what was expected.
+pub fn run(directory:&str,update:bool)->bool {
+    let mut programs=match read_dir(directory) {
+        Ok(entries)=>entries
+            .filter_map(|entry|entry.ok())
+            .map(|entry|entry.path())
+            .filter(|path|path.extension().is_some_and(|ext|ext=="fuzz"))
+            .collect::<Vec<_>>(),
+        Err(e)=>{
+            eprintln!("Error: could not read `{}`: {}",directory,e);
+            return false;
+        },
+    };
+    programs.sort();
+
+    let mut failed=0;
+    for program in programs.iter() {
+        let expected_path=program.with_extension("out");
+        let actual=match run_program(program) {
+            Ok(output)=>output,
+            Err(e)=>{
+                println!("FAILED {}: {}",program.display(),e);
+                failed+=1;
+                continue;
+            },
+        };
+        if update {
+            if let Err(e)=write(&expected_path,&actual) {
+                println!("FAILED {}: could not write `{}`: {}",program.display(),expected_path.display(),e);
+                failed+=1;
+            } else {
+                println!("updated {}",expected_path.display());
+            }
+            continue;
+        }
+        match read_to_string(&expected_path) {
+            Ok(expected) if expected==actual=>println!("ok {}",program.display()),
+            Ok(expected)=>{
+                println!("FAILED {}: the output changed",program.display());
+                print_diff(&expected,&actual);
+                failed+=1;
+            },
+            Err(_)=>{
+                println!("FAILED {}: there's no `{}`, run with `--update` to create it",program.display(),expected_path.display());
+                failed+=1;
+            },
+        }
+    }
+
+    if !update {
+        println!("\n{} passed, {} failed",programs.len()-failed,failed);
+    }
+    failed==0
+}
+/// Run a program in its own process and collect everything it prints, followed by any error.
+/// It's run from its own directory so the paths in errors don't depend on where the tests were
+/// started from.
+fn run_program(program:&Path)->Result<String,String> {
+    let exe=current_exe().map_err(|e|format!("could not find the interpreter: {}",e))?;
+    let mut command=Command::new(exe);
+    if let Some(directory)=program.parent().filter(|dir|!dir.as_os_str().is_empty()) {
+        command.current_dir(directory);
+    }
+    command.args(["--seed",SEED]);
+    let input=program.with_extension("in");
+    if input.exists() {
+        command.arg("--input").arg(input.file_name().unwrap());
+    }
+    let output=command.arg(program.file_name().unwrap())
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e|format!("could not run the interpreter: {}",e))?;
+    let mut text=String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok(text)
+}
+/// Print the lines that differ between `expected` and `actual`, with a few lines around them
+fn print_diff(expected:&str,actual:&str) {
+    let expected=expected.lines().collect::<Vec<_>>();
+    let actual=actual.lines().collect::<Vec<_>>();
+
+    // The longest common subsequence of lines from each position to the end
+    let mut lengths=vec![vec![0usize;actual.len()+1];expected.len()+1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lengths[i][j]=if expected[i]==actual[j] {
+                lengths[i+1][j+1]+1
+            } else {
+                lengths[i+1][j].max(lengths[i][j+1])
+            };
+        }
+    }
+    let mut lines=Vec::new();
+    let (mut i,mut j)=(0,0);
+    while i<expected.len()||j<actual.len() {
+        if i<expected.len()&&j<actual.len()&&expected[i]==actual[j] {
+            lines.push((' ',expected[i]));
+            i+=1;
+            j+=1;
+        } else if i<expected.len()&&(j==actual.len()||lengths[i+1][j]>=lengths[i][j+1]) {
+            lines.push(('-',expected[i]));
+            i+=1;
+        } else {
+            lines.push(('+',actual[j]));
+            j+=1;
+        }
+    }
+
+    let changed=lines.iter()
+        .enumerate()
+        .filter(|(_,(kind,_))|*kind!=' ')
+        .map(|(i,_)|i)
+        .collect::<Vec<_>>();
+    let mut last_shown=None;
+    for (i,(kind,line)) in lines.iter().enumerate() {
+        let near_change=changed.iter().any(|c|c.abs_diff(i)<=CONTEXT_LINES);
+        if !near_change {
+            continue;
+        }
+        if last_shown.map_or(i>0,|last|last+1<i) {
+            println!("  ...");
+        }
+        println!("  {} {}",kind,line);
+        last_shown=Some(i);
+    }
+}
@@ -5,7 +5,7 @@ use lovely::{
     value::Value,
     vm::execute,
 };
-use std::fs::File;
+use std::path::Path;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let example = vec![
@@ -18,17 +18,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let dt = image::draw(&bytecode);
     dt.write_png("output.png")?;
 
-    let decoder = png::Decoder::new(File::open("output.png").unwrap());
-    let mut reader = decoder.read_info().unwrap();
-    let mut buf = vec![0; reader.output_buffer_size()];
-    let info = reader.next_frame(&mut buf).unwrap();
-    let bytes = &buf[..info.buffer_size()];
+    let bytecode = image::load(Path::new("output.png"))?;
 
-    let bytecode = image::decode(bytes, info.width as usize);
+    let example = decode(&bytecode)?;
 
-    let example = decode(&bytecode);
-
-    execute(&example);
+    execute(&example)?;
 
     Ok(())
 }
@@ -0,0 +1,309 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{bytecode::Op, value::Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Turns assembly text into ops. Every line holds an optional `label:`
+/// followed by an optional instruction, and `;` starts a comment:
+///
+/// ```text
+/// push 0
+/// loop:
+///     push "hi" ; strings, numbers and true/false can be pushed
+///     print
+///     jmp loop
+/// ```
+pub fn assemble(source: &str) -> Result<Vec<Op>, AsmError> {
+    let mut ops = Vec::new();
+    let mut labels = Labels::default();
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let error = |message: String| AsmError {
+            line: line_number,
+            message,
+        };
+
+        let mut rest = strip_comment(line).trim();
+        if let Some((name, after)) = rest.split_once(':') {
+            if is_identifier(name.trim()) {
+                let id = labels.define(name.trim(), line_number).map_err(error)?;
+                ops.push(Op::Label(id));
+                rest = after.trim();
+            }
+        }
+        if rest.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operand) = match rest.split_once(char::is_whitespace) {
+            Some((mnemonic, operand)) => (mnemonic, operand.trim()),
+            None => (rest, ""),
+        };
+        let mnemonic = mnemonic.to_lowercase();
+
+        let op = match mnemonic.as_str() {
+            "push" => Op::Constant(parse_value(operand).map_err(error)?),
+            "load" | "store" | "gload" | "gstore" => {
+                let slot = operand.parse().map_err(|_| {
+                    error(format!(
+                        "`{}` expects a slot from 0 to 255, found `{}`",
+                        mnemonic, operand
+                    ))
+                })?;
+                match mnemonic.as_str() {
+                    "load" => Op::Load(slot),
+                    "store" => Op::Store(slot),
+                    "gload" => Op::GLoad(slot),
+                    _ => Op::GStore(slot),
+                }
+            }
+            "jmp" | "jmpif" | "call" => {
+                if !is_identifier(operand) {
+                    return Err(error(format!(
+                        "`{}` expects a label, found `{}`",
+                        mnemonic, operand
+                    )));
+                }
+                let id = labels.reference(operand, line_number).map_err(error)?;
+                match mnemonic.as_str() {
+                    "jmp" => Op::Jmp(id),
+                    "jmpif" => Op::JmpIf(id),
+                    _ => Op::Call(id),
+                }
+            }
+            _ => {
+                let op = simple_op(&mnemonic)
+                    .ok_or_else(|| error(format!("unknown instruction `{}`", mnemonic)))?;
+                if !operand.is_empty() {
+                    return Err(error(format!("`{}` takes no operand", mnemonic)));
+                }
+                op
+            }
+        };
+        ops.push(op);
+    }
+
+    if let Some((name, line)) = labels.first_undefined() {
+        return Err(AsmError {
+            line,
+            message: format!("jump to undefined label `{}`", name),
+        });
+    }
+
+    Ok(ops)
+}
+
+/// Turns ops back into text that `assemble` accepts. Labels are named after
+/// their id since the names don't survive encoding.
+pub fn disassemble(ops: &[Op]) -> String {
+    let mut result = String::new();
+
+    for op in ops {
+        let line = match op {
+            Op::Label(id) => format!("L{}:", id),
+            Op::Jmp(id) => format!("    jmp L{}", id),
+            Op::JmpIf(id) => format!("    jmpif L{}", id),
+            Op::Call(id) => format!("    call L{}", id),
+            Op::Load(slot) | Op::Store(slot) | Op::GLoad(slot) | Op::GStore(slot) => {
+                format!("    {} {}", mnemonic(op), slot)
+            }
+            Op::Constant(value) => format!("    push {}", format_value(value)),
+            _ => format!("    {}", mnemonic(op)),
+        };
+        result.push_str(&line);
+        result.push('\n');
+    }
+
+    result
+}
+
+pub fn mnemonic(op: &Op) -> &'static str {
+    match op {
+        Op::Nop => "nop",
+        Op::Constant(_) => "push",
+        Op::Pop => "pop",
+        Op::Dup => "dup",
+        Op::Add => "add",
+        Op::Sub => "sub",
+        Op::Mul => "mul",
+        Op::Div => "div",
+        Op::Print => "print",
+        Op::Lt => "lt",
+        Op::Gt => "gt",
+        Op::Eq => "eq",
+        Op::Not => "not",
+        Op::And => "and",
+        Op::Or => "or",
+        Op::Label(_) => "label",
+        Op::Jmp(_) => "jmp",
+        Op::JmpIf(_) => "jmpif",
+        Op::Call(_) => "call",
+        Op::Ret => "ret",
+        Op::Load(_) => "load",
+        Op::Store(_) => "store",
+        Op::GLoad(_) => "gload",
+        Op::GStore(_) => "gstore",
+    }
+}
+
+fn simple_op(mnemonic: &str) -> Option<Op> {
+    Some(match mnemonic {
+        "nop" => Op::Nop,
+        "pop" => Op::Pop,
+        "dup" => Op::Dup,
+        "add" => Op::Add,
+        "sub" => Op::Sub,
+        "mul" => Op::Mul,
+        "div" => Op::Div,
+        "print" => Op::Print,
+        "lt" => Op::Lt,
+        "gt" => Op::Gt,
+        "eq" => Op::Eq,
+        "not" => Op::Not,
+        "and" => Op::And,
+        "or" => Op::Or,
+        "ret" => Op::Ret,
+        _ => return None,
+    })
+}
+
+/// Label names are handed out ids in the order they're first seen.
+#[derive(Default)]
+struct Labels {
+    ids: HashMap<String, u16>,
+    defined: HashMap<String, usize>,
+    first_use: HashMap<String, usize>,
+}
+
+impl Labels {
+    fn id(&mut self, name: &str) -> Result<u16, String> {
+        if let Some(id) = self.ids.get(name) {
+            return Ok(*id);
+        }
+        let id = u16::try_from(self.ids.len())
+            .map_err(|_| "too many labels, at most 65536 are supported".to_string())?;
+        self.ids.insert(name.to_string(), id);
+        Ok(id)
+    }
+
+    fn define(&mut self, name: &str, line: usize) -> Result<u16, String> {
+        if let Some(first) = self.defined.get(name) {
+            return Err(format!(
+                "label `{}` is already defined on line {}",
+                name, first
+            ));
+        }
+        self.defined.insert(name.to_string(), line);
+        self.id(name)
+    }
+
+    fn reference(&mut self, name: &str, line: usize) -> Result<u16, String> {
+        self.first_use.entry(name.to_string()).or_insert(line);
+        self.id(name)
+    }
+
+    /// The first jump to a label that never got defined, and its line.
+    fn first_undefined(&self) -> Option<(&String, usize)> {
+        self.first_use
+            .iter()
+            .filter(|(name, _)| !self.defined.contains_key(*name))
+            .map(|(name, line)| (name, *line))
+            .min_by_key(|(_, line)| *line)
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Everything before a `;` that isn't inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(operand: &str) -> Result<Value, String> {
+    match operand {
+        "" => Err("`push` expects a value".to_string()),
+        "true" => Ok(Value::Bool(true)),
+        "false" => Ok(Value::Bool(false)),
+        _ if operand.starts_with('"') => parse_string(operand).map(Value::String),
+        _ => operand
+            .parse()
+            .map(Value::Number)
+            .map_err(|_| format!("`{}` is not a number, string or bool", operand)),
+    }
+}
+
+fn parse_string(operand: &str) -> Result<String, String> {
+    let mut result = String::new();
+    let mut chars = operand[1..].chars();
+    loop {
+        match chars.next() {
+            Some('"') => break,
+            Some('\\') => result.push(match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('\\') => '\\',
+                Some('"') => '"',
+                Some(other) => return Err(format!("unknown escape `\\{}`", other)),
+                None => return Err("unterminated string".to_string()),
+            }),
+            Some(c) => result.push(c),
+            None => return Err("unterminated string".to_string()),
+        }
+    }
+    if !chars.as_str().trim().is_empty() {
+        return Err(format!("unexpected `{}` after string", chars.as_str().trim()));
+    }
+    Ok(result)
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Number(number) => format!("{:?}", number),
+        Value::String(string) => {
+            let mut result = String::from('"');
+            for c in string.chars() {
+                match c {
+                    '\n' => result.push_str("\\n"),
+                    '\t' => result.push_str("\\t"),
+                    '\\' | '"' => {
+                        result.push('\\');
+                        result.push(c);
+                    }
+                    _ => result.push(c),
+                }
+            }
+            result.push('"');
+            result
+        }
+        Value::Bool(boolean) => boolean.to_string(),
+    }
+}
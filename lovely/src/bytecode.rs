@@ -1,3 +1,5 @@
+use std::fmt;
+
 use crate::value::Value;
 
 #[derive(Debug, Clone)]
@@ -23,9 +25,22 @@ pub enum Op {
     And,
     Or,
 
-    Label(u8),
-    Jmp(u8),
-    JmpIf(u8),
+    Label(u16),
+    Jmp(u16),
+    JmpIf(u16),
+
+    /// Jump to a label with a fresh frame of locals, coming back at `Ret`.
+    Call(u16),
+    Ret,
+
+    /// Push a local of the current frame.
+    Load(u8),
+    /// Pop into a local of the current frame.
+    Store(u8),
+    /// Push a global.
+    GLoad(u8),
+    /// Pop into a global.
+    GStore(u8),
 }
 
 impl Op {
@@ -42,9 +57,18 @@ impl Op {
             12 => Some(Self::Gt),
             13 => Some(Self::Eq),
             14 => Some(Self::Not),
-            15 => Some(Self::Or),
-            16 => Some(Self::And),
+            15 => Some(Self::And),
+            16 => Some(Self::Or),
             17 => Some(Self::Dup),
+            19 => Some(Self::Ret),
+            _ => None,
+        }
+    }
+
+    /// The label an op defines or goes to.
+    pub fn label(&self) -> Option<u16> {
+        match self {
+            Op::Label(id) | Op::Jmp(id) | Op::JmpIf(id) | Op::Call(id) => Some(*id),
             _ => None,
         }
     }
@@ -69,10 +93,71 @@ impl Op {
             Op::And => 15,
             Op::Or => 16,
             Op::Dup => 17,
+            Op::Call(_) => 18,
+            Op::Ret => 19,
+            Op::Load(_) => 20,
+            Op::Store(_) => 21,
+            Op::GLoad(_) => 22,
+            Op::GStore(_) => 23,
         }
     }
 }
 
+/// Joins two programs into one that runs `first` and then `second`. The labels
+/// of `second` are renumbered to not clash with those of `first`, which gives
+/// `None` if there end up being too many.
+///
+/// `first` is called like a function, so wherever it would have ended, be it a
+/// `ret` at its top level or running off its end, carries on into `second`:
+///
+/// ```text
+///     call <first>
+///     jmp <second>
+/// <first>:
+///     ...first
+///     ret
+/// <second>:
+///     ...second
+/// ```
+pub fn concat(first: &[Op], second: &[Op]) -> Option<Vec<Op>> {
+    let offset = first
+        .iter()
+        .filter_map(Op::label)
+        .max()
+        .map_or(0, |id| id as u32 + 1);
+    let shift = |id: u16| u16::try_from(id as u32 + offset).ok();
+
+    let mut second_ops = Vec::with_capacity(second.len());
+    for op in second {
+        second_ops.push(match op {
+            Op::Label(id) => Op::Label(shift(*id)?),
+            Op::Jmp(id) => Op::Jmp(shift(*id)?),
+            Op::JmpIf(id) => Op::JmpIf(shift(*id)?),
+            Op::Call(id) => Op::Call(shift(*id)?),
+            _ => op.clone(),
+        });
+    }
+
+    let next = second_ops
+        .iter()
+        .filter_map(Op::label)
+        .max()
+        .map_or(offset, |id| id as u32 + 1);
+    let first_start = u16::try_from(next).ok()?;
+    let second_start = u16::try_from(next + 1).ok()?;
+
+    let mut result = Vec::with_capacity(first.len() + second.len() + 5);
+    result.push(Op::Call(first_start));
+    result.push(Op::Jmp(second_start));
+    result.push(Op::Label(first_start));
+    result.extend_from_slice(first);
+    result.push(Op::Ret);
+    result.push(Op::Label(second_start));
+    result.extend(second_ops);
+
+    Some(result)
+}
+
 pub fn encode(ops: &[Op]) -> Vec<u8> {
     let mut result = Vec::new();
 
@@ -101,7 +186,12 @@ pub fn encode(ops: &[Op]) -> Vec<u8> {
                     }
                 }
             }
-            Op::Label(id) | Op::Jmp(id) | Op::JmpIf(id) => result.push(*id),
+            Op::Label(id) | Op::Jmp(id) | Op::JmpIf(id) | Op::Call(id) => {
+                result.extend_from_slice(&id.to_le_bytes())
+            }
+            Op::Load(slot) | Op::Store(slot) | Op::GLoad(slot) | Op::GStore(slot) => {
+                result.push(*slot)
+            }
             Op::Pop
             | Op::Nop
             | Op::Add
@@ -115,85 +205,148 @@ pub fn encode(ops: &[Op]) -> Vec<u8> {
             | Op::Not
             | Op::And
             | Op::Or
-            | Op::Dup => {}
+            | Op::Dup
+            | Op::Ret => {}
         }
     }
 
     result
 }
 
-pub fn decode(bytes: &[u8]) -> Vec<Op> {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownOp { offset: usize, id: u8 },
+    UnknownType { offset: usize, id: u8 },
+    /// An op's operand runs past the end of the bytes.
+    Truncated { offset: usize },
+    InvalidUtf8 { offset: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnknownOp { offset, id } => {
+                write!(f, "unknown op id {} at byte {}", id, offset)
+            }
+            DecodeError::UnknownType { offset, id } => {
+                write!(f, "unknown constant type {} at byte {}", id, offset)
+            }
+            DecodeError::Truncated { offset } => {
+                write!(f, "the op at byte {} is cut off by the end of the program", offset)
+            }
+            DecodeError::InvalidUtf8 { offset } => {
+                write!(f, "the string at byte {} isn't valid UTF-8", offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub fn decode(bytes: &[u8]) -> Result<Vec<Op>, DecodeError> {
     let mut result = Vec::<Op>::new();
 
     let mut cursor = 0;
     while cursor < bytes.len() {
-        match bytes[cursor] {
+        let start = cursor;
+        // Takes the next `n` bytes of the op starting at `start`.
+        let mut take = |n: usize| {
+            let taken = cursor
+                .checked_add(n)
+                .and_then(|end| bytes.get(cursor..end))
+                .ok_or(DecodeError::Truncated { offset: start })?;
+            cursor += n;
+            Ok(taken)
+        };
+
+        let id = take(1)?[0];
+        let op = match id {
             1 => {
-                cursor += 1;
-                match bytes[cursor] {
+                let type_id = take(1)?[0];
+                match type_id {
                     0 => {
-                        cursor += 1;
-                        result.push(Op::Constant(Value::Number(f64::from_le_bytes([
-                            bytes[cursor],
-                            bytes[cursor + 1],
-                            bytes[cursor + 2],
-                            bytes[cursor + 3],
-                            bytes[cursor + 4],
-                            bytes[cursor + 5],
-                            bytes[cursor + 6],
-                            bytes[cursor + 7],
-                        ]))));
-                        cursor += 8;
+                        let number = take(8)?.try_into().unwrap();
+                        Op::Constant(Value::Number(f64::from_le_bytes(number)))
                     }
                     1 => {
-                        cursor += 1;
-                        let length = u64::from_le_bytes([
-                            bytes[cursor],
-                            bytes[cursor + 1],
-                            bytes[cursor + 2],
-                            bytes[cursor + 3],
-                            bytes[cursor + 4],
-                            bytes[cursor + 5],
-                            bytes[cursor + 6],
-                            bytes[cursor + 7],
-                        ]) as usize;
-                        cursor += 8;
-
-                        let mut byte_string = vec![0u8; length];
-                        for i in 0..length {
-                            byte_string[i] = bytes[cursor + i];
-                        }
-                        cursor += length;
-
-                        let string = String::from_utf8(byte_string).unwrap();
-                        result.push(Op::Constant(Value::String(string)));
+                        let length = u64::from_le_bytes(take(8)?.try_into().unwrap());
+                        let length = usize::try_from(length)
+                            .map_err(|_| DecodeError::Truncated { offset: start })?;
+                        let string = String::from_utf8(take(length)?.to_vec())
+                            .map_err(|_| DecodeError::InvalidUtf8 { offset: start })?;
+                        Op::Constant(Value::String(string))
                     }
-                    2 => {
-                        cursor += 1;
-                        let boolean = if bytes[cursor] == 1 { true } else { false };
-                        result.push(Op::Constant(Value::Bool(boolean)));
+                    2 => Op::Constant(Value::Bool(take(1)?[0] == 1)),
+                    _ => {
+                        return Err(DecodeError::UnknownType {
+                            offset: start + 1,
+                            id: type_id,
+                        })
                     }
-                    _ => todo!(),
-                };
+                }
+            }
+            8 | 9 | 10 | 18 => {
+                let label = u16::from_le_bytes(take(2)?.try_into().unwrap());
+                match id {
+                    8 => Op::Label(label),
+                    9 => Op::Jmp(label),
+                    10 => Op::JmpIf(label),
+                    _ => Op::Call(label),
+                }
             }
-            8 | 9 | 10 => {
-                cursor += 1;
+            20 => Op::Load(take(1)?[0]),
+            21 => Op::Store(take(1)?[0]),
+            22 => Op::GLoad(take(1)?[0]),
+            23 => Op::GStore(take(1)?[0]),
+            _ => Op::from_op_id(id).ok_or(DecodeError::UnknownOp { offset: start, id })?,
+        };
+        result.push(op);
+    }
 
-                result.push(match bytes[cursor - 1] {
-                    8 => Op::Label(bytes[cursor]),
-                    9 => Op::Jmp(bytes[cursor]),
-                    10 => Op::JmpIf(bytes[cursor]),
-                    _ => unreachable!(),
-                });
+    Ok(result)
+}
 
-                cursor += 1;
-            }
-            _ => {
-                result.push(Op::from_op_id(bytes[cursor]).unwrap());
-                cursor += 1;
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm::assemble, vm};
+
+    /// Adds one to global 0, with a label that clashes with `double`'s.
+    const SECOND: &str = "
+    gload 0
+    call inc
+    ret
+inc:
+    push 1
+    add
+    ret
+";
+
+    #[test]
+    fn concat_runs_both_after_a_top_level_ret() {
+        let first = assemble(
+            "
+    call double
+    gstore 0
+    ret
+double:
+    push 21
+    push 2
+    mul
+    ret
+",
+        )
+        .unwrap();
+
+        let both = concat(&first, &assemble(SECOND).unwrap()).unwrap();
+        assert_eq!(vm::execute(&both).unwrap(), Some(Value::Number(43.0)));
     }
 
-    result
+    #[test]
+    fn concat_runs_both_after_running_off_the_end() {
+        let first = assemble("push 20\ngstore 0\n").unwrap();
+
+        let both = concat(&first, &assemble(SECOND).unwrap()).unwrap();
+        assert_eq!(vm::execute(&both).unwrap(), Some(Value::Number(21.0)));
+    }
 }
@@ -1,119 +1,621 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+};
+
 use raqote::*;
 
+/// Every other point of a grid with 8 levels a channel, so neighbouring
+/// colours are about 51 apart.
 const COLORS: [&str; 256] = [
-    "#c4b9b8", "#879f84", "#a6814c", "#ff9966", "#aa1155", "#7a81ff", "#8e473b", "#f3dfb6",
-    "#dfac4c", "#4b3621", "#feff32", "#a57c5b", "#52b4ca", "#fffc79", "#879877", "#bb1133",
-    "#eee9d9", "#52b4d3", "#ffff81", "#4f4554", "#c68f65", "#d2e7ca", "#0000ff", "#2ee8bb",
-    "#eebb88", "#eddd59", "#7f5f00", "#eeaa11", "#35fa00", "#ffefd6", "#bb11aa", "#dab4cc",
-    "#fff0db", "#987d73", "#c4fff7", "#eae0c8", "#e2c779", "#ff4d00", "#334d41", "#f83800",
-    "#ddedbd", "#ffdd44", "#efd7ab", "#66eeee", "#aa3333", "#006663", "#ffb75f", "#c6bb9c",
-    "#685c53", "#fff1dc", "#313390", "#ff6f01", "#8f8ce7", "#cf0234", "#ff9966", "#5f5537",
-    "#342931", "#f6eeed", "#fe019a", "#772299", "#ff6ec7", "#f2e6e1", "#ffb49b", "#252525",
-    "#dcf1c7", "#674876", "#eeaa55", "#c7031e", "#7a2e4d", "#9f0000", "#eed683", "#e6dee6",
-    "#fdd7e4", "#95859c", "#bfa58a", "#16141c", "#7b9a6d", "#cc6666", "#ddece0", "#e398af",
-    "#004488", "#fea993", "#6d5698", "#ef1de7", "#ff028d", "#eae9e7", "#f6e2ea", "#b8b8f8",
-    "#ccddcc", "#b06500", "#ecebe5", "#7fbb9e", "#88b5c4", "#93c572", "#dddd88", "#805b87",
-    "#698890", "#ebe2cf", "#ee2222", "#c95a49", "#220011", "#f1ebc8", "#006400", "#553b39",
-    "#ffa177", "#cbc5c6", "#738f5d", "#32575d", "#fed55d", "#a2bffe", "#98333a", "#d7e7d0",
-    "#958b84", "#ee0000", "#3b2b2c", "#ff8656", "#f0fff0", "#44232f", "#faebd7", "#786e38",
-    "#080813", "#5b6f55", "#99c5c4", "#332e2e", "#7b4d3a", "#536267", "#eff0d3", "#f4d493",
-    "#fcd917", "#e16233", "#f8e0e7", "#e59b34", "#ebe5d0", "#393540", "#e4f3e0", "#d6d7d2",
-    "#c19a13", "#d29380", "#babfbc", "#3b638c", "#ffe29b", "#99eeff", "#eebb33", "#4a3b6a",
-    "#c6bbdb", "#ab6f60", "#ff9b87", "#b08f42", "#a67283", "#b1832f", "#fedbb7", "#fcd7ba",
-    "#ee3366", "#89a203", "#484a46", "#f878f8", "#b66325", "#c1f80a", "#6dbac0", "#4b373a",
-    "#03012d", "#ff2600", "#555570", "#ff7a00", "#8edacc", "#ffcc77", "#cfac47", "#a15325",
-    "#ff4466", "#f9f1dd", "#816d5e", "#5f6957", "#f3e0d8", "#006380", "#c26157", "#f9e3b4",
-    "#c88ca4", "#b87333", "#ea9073", "#ee1133", "#e4d9c5", "#dbe7e3", "#ff0e0e", "#eebe1b",
-    "#d8caa9", "#9bc2b1", "#b0003c", "#bae5d6", "#e38fac", "#f6cbca", "#ee4433", "#73383c",
-    "#3f4250", "#000066", "#ffff33", "#bda58b", "#e0b0ff", "#acddaf", "#a9afaa", "#cee1f2",
-    "#7ad7ad", "#e5dae1", "#906a54", "#938b4b", "#86c4da", "#a17a83", "#008e80", "#ebebeb",
-    "#883377", "#9a0eea", "#11cc55", "#eecc44", "#aaff32", "#e2c9ce", "#f2ab46", "#fd8f79",
-    "#92898a", "#787489", "#a6ab9b", "#cef0cc", "#443388", "#fedc57", "#ed4b00", "#a58459",
-    "#553311", "#ffb07c", "#d4ffff", "#534b4f", "#74857f", "#007fff", "#332266", "#bc6f37",
-    "#aaaa77", "#cce2f3", "#fff8dc", "#5c5d5d", "#f3f4d9", "#c7bba4", "#cf758a", "#fbec5d",
-    "#fffbf8", "#b7d2e3", "#eeee66", "#f6efe1", "#ffe39b", "#4e312d", "#e75480", "#bbb3a2",
-    "#3c2f23", "#8f7f85", "#e3cdc2", "#f3efcd", "#152eff", "#d9eae5", "#dabe82", "#ce2029",
+    "#926dff", "#6ddb6d", "#6db600", "#ff926d", "#b62449", "#6d92ff", "#924949", "#4924b6",
+    "#92db00", "#6d2400", "#dbff24", "#49db49", "#49b6b6", "#24ff49", "#49db92", "#b6006d",
+    "#00b6b6", "#4992db", "#ffff92", "#490092", "#b6926d", "#24ffdb", "#0000db", "#24dbb6",
+    "#24db6d", "#dbdb49", "#6d6d00", "#ffb600", "#24ff00", "#2400b6", "#b600b6", "#dbb6b6",
+    "#0024b6", "#926d6d", "#49ffff", "#4949db", "#dbb66d", "#24b600", "#246d49", "#db0092",
+    "#dbffb6", "#ffff00", "#6d24db", "#6ddbff", "#b62400", "#006d6d", "#24b649", "#92ff6d",
+    "#499200", "#00db00", "#242492", "#ff6d00", "#9292db", "#db0049", "#db00db", "#496d24",
+    "#490049", "#6d00ff", "#ff00b6", "#6d2492", "#ff6ddb", "#2449ff", "#ffb692", "#242400",
+    "#24ff92", "#6d496d", "#49db00", "#b60024", "#6d2449", "#920000", "#49ff24", "#9224ff",
+    "#ff49ff", "#926db6", "#49ff6d", "#002424", "#6d926d", "#db6d6d", "#2492ff", "#ff92b6",
+    "#004992", "#db49db", "#6d49b6", "#ff24db", "#ff006d", "#00ffff", "#ffdbff", "#b692ff",
+    "#49ffb6", "#b66d00", "#0092db", "#6db692", "#6d92b6", "#92b66d", "#dbdb92", "#924992",
+    "#499292", "#246ddb", "#ff2492", "#db4949", "#240024", "#00ff6d", "#004900", "#006d24",
+    "#b624db", "#db6dff", "#6db649", "#24496d", "#ffdb6d", "#92b6ff", "#922424", "#24b6db",
+    "#9249db", "#db0000", "#24006d", "#ff6d49", "#009200", "#492424", "#4900db", "#926d24",
+    "#000000", "#499249", "#92ffb6", "#244924", "#6d4924", "#496d6d", "#2449b6", "#00db92",
+    "#ffdb24", "#db6d24", "#b600ff", "#db9249", "#00ffb6", "#242449", "#00dbdb", "#dbdbdb",
+    "#db9200", "#db9292", "#6dff92", "#246d92", "#009249", "#92ffff", "#dbdb00", "#6d006d",
+    "#b6b6db", "#9224b6", "#ff49b6", "#92b624", "#b66d92", "#b69224", "#ffdbb6", "#6d00b6",
+    "#db246d", "#929200", "#494949", "#ff92ff", "#924900", "#b6ff00", "#6db6db", "#246d00",
+    "#000049", "#ff2400", "#496db6", "#ff9224", "#92dbdb", "#24db24", "#b6b649", "#b64924",
+    "#ff496d", "#2400ff", "#49b624", "#6d6d49", "#4924ff", "#006db6", "#b6496d", "#00b66d",
+    "#b692b6", "#b6b600", "#db24b6", "#ff2449", "#2492b6", "#496dff", "#ff0024", "#dbb624",
+    "#24b692", "#92b6b6", "#920049", "#b6ffdb", "#db6db6", "#ffb6db", "#ff4924", "#920092",
+    "#004949", "#00246d", "#ffff49", "#6dff49", "#dbb6ff", "#b6dbb6", "#b66ddb", "#49b6ff",
+    "#6ddbb6", "#6d49ff", "#6d9224", "#929249", "#6dffdb", "#b62492", "#009292", "#00b6ff",
+    "#92246d", "#9200db", "#00db49", "#b6db24", "#b6ff49", "#db92db", "#ffb649", "#ff6d92",
+    "#929292", "#6d6d92", "#92db92", "#b6ff92", "#494992", "#6dff00", "#db4900", "#6ddb24",
+    "#494900", "#ff00ff", "#dbffff", "#249224", "#49b66d", "#006dff", "#49246d", "#b66d49",
+    "#92db49", "#b6dbff", "#ffffdb", "#24926d", "#00b624", "#b649ff", "#b649b6", "#92ff24",
+    "#000092", "#49dbdb", "#dbff6d", "#0049db", "#00ff24", "#6d0024", "#db4992", "#b6b692",
+    "#490000", "#6d6ddb", "#db24ff", "#2424db", "#0024ff", "#24dbff", "#b6db6d", "#db2424",
 ];
 
+// Every byte needs its own colour, and they have to be far enough apart
+// that a tile within `MAX_COLOR_DISTANCE` of one is always read as it.
+const _: () = assert!(
+    min_color_distance_squared() >= ((MIN_COLOR_CONFIDENCE + 1) * MAX_COLOR_DISTANCE).pow(2),
+    "COLORS has colours too close together"
+);
+
+const fn hex_digit(digit: u8) -> u32 {
+    match digit {
+        b'0'..=b'9' => (digit - b'0') as u32,
+        _ => (digit - b'a' + 10) as u32,
+    }
+}
+
+/// The squared distance between the two closest colours in `COLORS`.
+const fn min_color_distance_squared() -> u32 {
+    let mut min = u32::MAX;
+    let mut i = 0;
+    while i < COLORS.len() {
+        let mut j = i + 1;
+        while j < COLORS.len() {
+            let (a, b) = (COLORS[i].as_bytes(), COLORS[j].as_bytes());
+            let mut distance = 0;
+            let mut k = 1;
+            while k < a.len() {
+                let x = hex_digit(a[k]) * 16 + hex_digit(a[k + 1]);
+                let y = hex_digit(b[k]) * 16 + hex_digit(b[k + 1]);
+                distance += x.abs_diff(y).pow(2);
+                k += 2;
+            }
+            if distance < min {
+                min = distance;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    min
+}
+
+/// The first row of tiles is a header:
+///
+/// | tiles | contents                                   |
+/// |:------|:-------------------------------------------|
+/// | 0-1   | `LV`                                       |
+/// | 2     | the format version                         |
+/// | 3-6   | the program length in bytes, little endian |
+/// | 7-10  | CRC-32 of the program, little endian       |
+///
+/// and the rest of the row is padding. The program starts on the second
+/// row, anything after it is padding too.
+const MAGIC: &[u8; 2] = b"LV";
+pub const VERSION: u8 = 2;
+const HEADER_LEN: usize = 11;
+
+/// CRC-32 as used by PNG and zip.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// The header for `bytes`.
+fn header(bytes: &[u8]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.push(VERSION);
+    header.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    header.extend_from_slice(&crc32(bytes).to_le_bytes());
+    header
+}
+
+/// The program length and checksum from a header.
+fn read_header(header: &[u8]) -> Result<(usize, u32), ImageError> {
+    if header.len() < HEADER_LEN || &header[..2] != MAGIC {
+        return Err(ImageError::NotAProgram);
+    }
+    if header[2] != VERSION {
+        return Err(ImageError::UnsupportedVersion(header[2]));
+    }
+
+    let length = u32::from_le_bytes(header[3..7].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(header[7..11].try_into().unwrap());
+    Ok((length, checksum))
+}
+
+fn verify(bytes: &[u8], checksum: u32) -> Result<Vec<u8>, ImageError> {
+    let actual = crc32(bytes);
+    if actual != checksum {
+        return Err(ImageError::ChecksumMismatch {
+            expected: checksum,
+            actual,
+        });
+    }
+    Ok(bytes.to_vec())
+}
+
+/// The tiles for `bytes`, row by row, along with how many there are to a row.
+pub(crate) fn frame(bytes: &[u8]) -> (Vec<u8>, usize) {
+    // Square, with a row for the header and enough left for the program.
+    let mut tiles_per_row = HEADER_LEN;
+    while tiles_per_row * (tiles_per_row - 1) < bytes.len() {
+        tiles_per_row += 1;
+    }
+
+    let mut tiles = header(bytes);
+    tiles.resize(tiles_per_row, 0);
+    tiles.extend_from_slice(bytes);
+    tiles.resize(tiles_per_row * tiles_per_row, 0);
+
+    (tiles, tiles_per_row)
+}
+
+/// Checks the header on a grid of tiles and takes the program out of it.
+fn unframe(tiles: &[u8], tiles_per_row: usize) -> Result<Vec<u8>, ImageError> {
+    let (length, checksum) = read_header(&tiles[..tiles_per_row])?;
+    let bytes = tiles
+        .get(tiles_per_row..tiles_per_row + length)
+        .ok_or(ImageError::TooShort {
+            length,
+            room: tiles.len() - tiles_per_row,
+        })?;
+    verify(bytes, checksum)
+}
+
+/// The low bits of each color channel that `embed` writes a program into.
+const EMBED_MASK: u8 = 0b11;
+const EMBED_BITS: usize = 2;
+
+/// Hides `bytes` in the low bits of the colors of an RGBA photo, which `decode`
+/// then finds in place of tiles. Alpha is left alone, and the photo has to stay
+/// a lossless PNG for the program to survive.
+pub fn embed(photo: &mut [u8], bytes: &[u8]) -> Result<(), ImageError> {
+    let mut data = header(bytes);
+    data.extend_from_slice(bytes);
+
+    let room = photo.len() / 4 * 3 * EMBED_BITS / 8;
+    if data.len() > room {
+        return Err(ImageError::PhotoTooSmall {
+            needed: data.len(),
+            room,
+        });
+    }
+
+    let mut bits = data
+        .iter()
+        .flat_map(|byte| (0..8 / EMBED_BITS).map(move |i| (byte >> (i * EMBED_BITS)) & EMBED_MASK));
+    for (i, channel) in photo.iter_mut().enumerate() {
+        if i % 4 == 3 {
+            continue;
+        }
+        match bits.next() {
+            Some(bits) => *channel = (*channel & !EMBED_MASK) | bits,
+            None => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// The program `embed` hid in an RGBA image, or `None` if there isn't one.
+fn extract(pixels: &[u8]) -> Option<Result<Vec<u8>, ImageError>> {
+    let mut bits = pixels
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| i % 4 != 3)
+        .map(|(_, channel)| channel & EMBED_MASK);
+    let mut read = |n: usize| -> Option<Vec<u8>> {
+        (0..n)
+            .map(|_| {
+                let mut byte = 0;
+                for i in 0..8 / EMBED_BITS {
+                    byte |= bits.next()? << (i * EMBED_BITS);
+                }
+                Some(byte)
+            })
+            .collect()
+    };
+
+    let header = read(HEADER_LEN)?;
+    // Anything but our magic is an ordinary image.
+    if &header[..2] != MAGIC {
+        return None;
+    }
+    Some(read_header(&header).and_then(|(length, checksum)| {
+        let bytes = read(length).ok_or(ImageError::TooShort {
+            length,
+            room: pixels.len() / 4 * 3 * EMBED_BITS / 8 - HEADER_LEN,
+        })?;
+        verify(&bytes, checksum)
+    }))
+}
+
+/// Draws `bytes` as a square of tiles, one colour per byte, behind a header
+/// row that says how long it is and how to check it.
 pub fn draw(bytes: &[u8]) -> DrawTarget {
-    let tile_width = (bytes.len() as f32).sqrt().floor() + 1.0;
-    let size = tile_width.powi(2);
+    let (tiles, tiles_per_row) = frame(bytes);
+    // Tiles are as wide in pixels as there are of them to a row.
+    let tile_width = tiles_per_row as f32;
+    let size = tiles_per_row * tiles_per_row;
 
     let mut dt = DrawTarget::new(size as i32, size as i32);
 
-    let mut current_x = 0.0f32;
-    let mut current_y = 0.0f32;
-    for byte in bytes {
+    for (i, byte) in tiles.iter().enumerate() {
         dt.fill_rect(
-            current_x,
-            current_y,
+            (i % tiles_per_row) as f32 * tile_width,
+            (i / tiles_per_row) as f32 * tile_width,
             tile_width,
             tile_width,
-            &Source::Solid(SolidSource {
-                r: u8::from_str_radix(&COLORS[*byte as usize][1..3], 16).unwrap(),
-                g: u8::from_str_radix(&COLORS[*byte as usize][3..5], 16).unwrap(),
-                b: u8::from_str_radix(&COLORS[*byte as usize][5..7], 16).unwrap(),
-                a: 0xFF,
-            }),
+            &Source::Solid(color(*byte)),
             &DrawOptions::new(),
         );
+    }
+
+    dt
+}
 
-        if current_x + tile_width == size {
-            current_x = 0.0;
-            current_y += tile_width;
-        } else {
-            current_x += tile_width;
+/// How far, as a distance in RGB, a tile may be from the palette colour it's
+/// read as. Further than this and it's more likely to be something else.
+const MAX_COLOR_DISTANCE: u32 = 16;
+/// How many times closer the nearest palette colour has to be than the next
+/// nearest for a tile to be read as it.
+const MIN_COLOR_CONFIDENCE: u32 = 2;
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    Png(png::DecodingError),
+    UnsupportedColorType(png::ColorType),
+    /// The pixel buffer is the wrong size for the dimensions it came with.
+    BadDimensions {
+        width: usize,
+        height: usize,
+        len: usize,
+    },
+    /// There's no way to cut the image into a square grid of single colour tiles.
+    NoGrid,
+    /// A tile isn't close enough to any colour in the palette.
+    UnknownColor {
+        row: usize,
+        column: usize,
+        color: [u8; 3],
+    },
+    /// The header row is missing, so this isn't a program.
+    NotAProgram,
+    UnsupportedVersion(u8),
+    /// The header says the program is longer than the image has room for.
+    TooShort {
+        length: usize,
+        room: usize,
+    },
+    ChecksumMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    /// The photo given to `embed` doesn't have enough pixels for the program.
+    PhotoTooSmall {
+        needed: usize,
+        room: usize,
+    },
+    /// A tile is about as close to two colours in the palette.
+    AmbiguousColor {
+        row: usize,
+        column: usize,
+        color: [u8; 3],
+        candidates: [u8; 2],
+    },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Io(err) => write!(f, "{}", err),
+            ImageError::Png(err) => write!(f, "not a valid PNG: {}", err),
+            ImageError::UnsupportedColorType(color_type) => {
+                write!(f, "unsupported PNG color type {:?}", color_type)
+            }
+            ImageError::BadDimensions { width, height, len } => write!(
+                f,
+                "{} bytes of pixels can't be a {}x{} RGBA image",
+                len, width, height
+            ),
+            ImageError::NoGrid => write!(f, "couldn't find a grid of tiles in the image"),
+            ImageError::UnknownColor { row, column, color } => write!(
+                f,
+                "the tile at row {}, column {} is #{:02x}{:02x}{:02x}, which isn't close to any color in the palette",
+                row, column, color[0], color[1], color[2]
+            ),
+            ImageError::NotAProgram => write!(f, "the image has no lovely header"),
+            ImageError::UnsupportedVersion(version) => write!(
+                f,
+                "the image is version {} of the format, only version {} is supported",
+                version, VERSION
+            ),
+            ImageError::TooShort { length, room } => write!(
+                f,
+                "the header says the program is {} bytes but there's only room for {}",
+                length, room
+            ),
+            ImageError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "the program is damaged, its checksum is {:08x} instead of {:08x}",
+                actual, expected
+            ),
+            ImageError::PhotoTooSmall { needed, room } => write!(
+                f,
+                "the photo only has room for {} bytes but the program needs {}",
+                room, needed
+            ),
+            ImageError::AmbiguousColor {
+                row,
+                column,
+                color,
+                candidates,
+            } => write!(
+                f,
+                "the tile at row {}, column {} is #{:02x}{:02x}{:02x}, which could be {} or {}",
+                row, column, color[0], color[1], color[2], COLORS[candidates[0] as usize], COLORS[candidates[1] as usize]
+            ),
         }
     }
+}
 
-    let num_tiles_left = size - (bytes.len() as f32);
-    for _ in 0..(num_tiles_left as usize) {
-        dt.fill_rect(
-            current_x,
-            current_y,
-            tile_width,
-            tile_width,
-            &Source::Solid(SolidSource {
-                r: u8::from_str_radix(&COLORS[0][1..3], 16).unwrap(),
-                g: u8::from_str_radix(&COLORS[0][3..5], 16).unwrap(),
-                b: u8::from_str_radix(&COLORS[0][5..7], 16).unwrap(),
-                a: 0xFF,
-            }),
-            &DrawOptions::new(),
-        );
+impl std::error::Error for ImageError {}
+
+impl From<io::Error> for ImageError {
+    fn from(err: io::Error) -> Self {
+        ImageError::Io(err)
+    }
+}
 
-        if current_x + tile_width == size {
-            current_x = 0.0;
-            current_y += tile_width;
-        } else {
-            current_x += tile_width;
+impl From<png::DecodingError> for ImageError {
+    fn from(err: png::DecodingError) -> Self {
+        ImageError::Png(err)
+    }
+}
+
+/// The color of the tile for `byte`.
+pub(crate) fn color(byte: u8) -> SolidSource {
+    let hex = COLORS[byte as usize];
+    SolidSource {
+        r: u8::from_str_radix(&hex[1..3], 16).unwrap(),
+        g: u8::from_str_radix(&hex[3..5], 16).unwrap(),
+        b: u8::from_str_radix(&hex[5..7], 16).unwrap(),
+        a: 0xFF,
+    }
+}
+
+fn palette() -> Vec<[u8; 3]> {
+    (0..=255)
+        .map(|byte| {
+            let color = color(byte);
+            [color.r, color.g, color.b]
+        })
+        .collect()
+}
+
+/// The two palette indices closest to `color`, with their squared distances.
+fn nearest2(palette: &[[u8; 3]], color: [u8; 3]) -> [(u8, u32); 2] {
+    let mut best = [(0, u32::MAX); 2];
+    for (idx, candidate) in palette.iter().enumerate() {
+        let distance = candidate
+            .iter()
+            .zip(color)
+            .map(|(a, b)| (*a as i32 - b as i32).pow(2) as u32)
+            .sum();
+        if distance < best[0].1 {
+            best = [(idx as u8, distance), best[0]];
+        } else if distance < best[1].1 {
+            best[1] = (idx as u8, distance);
         }
     }
+    best
+}
 
-    dt
+/// The palette index closest to `color`.
+fn nearest(palette: &[[u8; 3]], color: [u8; 3]) -> u8 {
+    nearest2(palette, color)[0].0
 }
 
-pub fn decode(image_bytes: &[u8], size: usize) -> Vec<u8> {
-    let mut result = Vec::new();
-    let tile_width = (size as f32).sqrt() as usize;
+/// An RGBA image being read as an `n` by `n` grid of tiles.
+struct Tiles<'a> {
+    pixels: &'a [u8],
+    width: usize,
+    height: usize,
+    palette: Vec<[u8; 3]>,
+}
+
+impl Tiles<'_> {
+    /// The color at `x`, `y` in tiles, where `0.5` is the middle of the first one.
+    fn sample(&self, n: usize, x: f64, y: f64) -> [u8; 3] {
+        let px = ((x * self.width as f64 / n as f64) as usize).min(self.width - 1);
+        let py = ((y * self.height as f64 / n as f64) as usize).min(self.height - 1);
+        let i = (py * self.width + px) * 4;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]]
+    }
+
+    /// Whether every tile reads as the same palette color all over. Smoothing
+    /// from resizing only touches the edges, so it's fine to look near them.
+    fn uniform(&self, n: usize) -> bool {
+        for row in 0..n {
+            for column in 0..n {
+                let (x, y) = (column as f64, row as f64);
+                let center = nearest(&self.palette, self.sample(n, x + 0.5, y + 0.5));
+                for (dx, dy) in [(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)] {
+                    if nearest(&self.palette, self.sample(n, x + dx, y + dy)) != center {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
 
-    let pixels = image_bytes.chunks(4).collect::<Vec<_>>();
+    /// How many tiles there are along each side. `draw` makes images whose
+    /// side is the square of that, otherwise the image has been resized and
+    /// the smallest grid that fits is the best guess.
+    fn detect(&self) -> Option<usize> {
+        let exact = (self.width as f64).sqrt().round() as usize;
+        if exact * exact == self.width && self.width == self.height && self.uniform(exact) {
+            return Some(exact);
+        }
+        (1..=self.width.min(self.height)).find(|n| self.uniform(*n))
+    }
+}
 
-    let mut cursor = 0;
-    for _ in 0..tile_width {
-        for _ in 0..tile_width {
-            let r = pixels[cursor][0];
-            let g = pixels[cursor][1];
-            let b = pixels[cursor][2];
-            let color_string = format!("#{:02x}{:02x}{:02x}", r, g, b);
+/// Reads the program back out of an RGBA image, checking it against its
+/// header. That's either a program `embed` hid in a photo or the tiles `draw`
+/// made. Tiles don't have to be exactly as drawn: the image can be resized
+/// and have its colors shifted a little.
+pub fn decode(image_bytes: &[u8], width: usize, height: usize) -> Result<Vec<u8>, ImageError> {
+    if width == 0 || height == 0 || image_bytes.len() != width * height * 4 {
+        return Err(ImageError::BadDimensions {
+            width,
+            height,
+            len: image_bytes.len(),
+        });
+    }
 
-            let idx = COLORS.iter().position(|el| *el == color_string);
-            result.push(idx.unwrap() as u8);
+    match extract(image_bytes) {
+        Some(Ok(bytes)) => Ok(bytes),
+        // The magic can turn up in tiles by chance, but if it isn't tiles
+        // either then it's a damaged embedded program.
+        Some(Err(err)) => decode_tiles(image_bytes, width, height).map_err(|_| err),
+        None => decode_tiles(image_bytes, width, height),
+    }
+}
+
+fn decode_tiles(image_bytes: &[u8], width: usize, height: usize) -> Result<Vec<u8>, ImageError> {
+    let tiles = Tiles {
+        pixels: image_bytes,
+        width,
+        height,
+        palette: palette(),
+    };
+    let n = tiles.detect().ok_or(ImageError::NoGrid)?;
 
-            cursor += tile_width;
+    let mut result = Vec::with_capacity(n * n);
+    for row in 0..n {
+        for column in 0..n {
+            let color = tiles.sample(n, column as f64 + 0.5, row as f64 + 0.5);
+            let [(byte, distance), (runner_up, runner_up_distance)] =
+                nearest2(&tiles.palette, color);
+            if distance > MAX_COLOR_DISTANCE.pow(2) {
+                return Err(ImageError::UnknownColor { row, column, color });
+            }
+            // Distances are squared, so is the confidence.
+            if distance * MIN_COLOR_CONFIDENCE.pow(2) > runner_up_distance {
+                return Err(ImageError::AmbiguousColor {
+                    row,
+                    column,
+                    color,
+                    candidates: [byte, runner_up],
+                });
+            }
+            result.push(byte);
         }
-        cursor += size * (tile_width - 1);
     }
 
-    result
+    unframe(&result, n)
+}
+
+/// Reads a PNG of any color type as RGBA, along with its width and height.
+pub fn load_rgba(path: &Path) -> Result<(Vec<u8>, usize, usize), ImageError> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    let bytes = &buf[..info.buffer_size()];
+
+    let rgba: Vec<u8> = match info.color_type {
+        png::ColorType::Rgba => bytes.to_vec(),
+        png::ColorType::Rgb => bytes
+            .chunks(3)
+            .flat_map(|p| [p[0], p[1], p[2], 0xFF])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => bytes
+            .chunks(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => bytes.iter().flat_map(|g| [*g, *g, *g, 0xFF]).collect(),
+        other => return Err(ImageError::UnsupportedColorType(other)),
+    };
+
+    Ok((rgba, info.width as usize, info.height as usize))
+}
+
+/// Writes RGBA pixels to a PNG.
+pub fn save_rgba(
+    path: &Path,
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        width as u32,
+        height as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(pixels)
+}
+
+/// Reads a PNG of any color type and decodes the program in it.
+pub fn load(path: &Path) -> Result<Vec<u8>, ImageError> {
+    let (rgba, width, height) = load_rgba(path)?;
+    decode(&rgba, width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tiles `draw` makes for `bytes` as RGBA, with every channel moved by `shift`.
+    fn shifted_tiles(bytes: &[u8], shift: i16) -> (Vec<u8>, usize, usize) {
+        let dt = draw(bytes);
+        let pixels = dt
+            .get_data()
+            .iter()
+            .flat_map(|argb| {
+                let [_, r, g, b] = argb.to_be_bytes();
+                [r, g, b]
+                    .map(|c| (c as i16 + shift).clamp(0, 255) as u8)
+                    .into_iter()
+                    .chain([0xFF])
+            })
+            .collect();
+        (pixels, dt.width() as usize, dt.height() as usize)
+    }
+
+    #[test]
+    fn decodes_shifted_colours() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        for shift in [-3, 3, -8, 8] {
+            let (pixels, width, height) = shifted_tiles(&bytes, shift);
+            assert_eq!(
+                decode(&pixels, width, height).unwrap(),
+                bytes,
+                "shifted by {}",
+                shift
+            );
+        }
+    }
 }
@@ -1,4 +1,6 @@
+pub mod asm;
 pub mod bytecode;
 pub mod image;
+pub mod source_map;
 pub mod value;
 pub mod vm;
@@ -0,0 +1,131 @@
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+use lovely::{asm, bytecode, image, source_map, vm};
+
+const USAGE: &str = "\
+Usage:
+    lovely assemble <file> [-o <out.png>]   Assemble a text program into a PNG.
+    lovely run <file.png>                   Decode a PNG and execute it.
+    lovely disasm <file.png>                Print the assembly of a PNG.
+    lovely concat <a.png> <b.png> -o <out.png>
+                                            Join two programs into one that runs both.
+    lovely embed <file.png> <photo.png> -o <out.png>
+                                            Hide a program in the low bits of a photo.
+    lovely map <file.png> [-o <out.png>]    Draw a program's tiles labelled with their ops.";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(err) = run(args) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(mut args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let command = match args.first().map(String::as_str) {
+        Some("assemble" | "run" | "disasm" | "concat" | "embed" | "map") => args.remove(0),
+        Some("help" | "-h" | "--help") => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        Some(other) => return Err(format!("unknown command `{}`\n\n{}", other, USAGE).into()),
+        None => return Err(format!("no command given\n\n{}", USAGE).into()),
+    };
+
+    let mut out_path = None;
+    let mut files = Vec::new();
+    let mut rest = args.into_iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "-o" => out_path = Some(rest.next().ok_or("expected a file name after `-o`")?),
+            _ => files.push(arg),
+        }
+    }
+
+    let wanted = match command.as_str() {
+        "concat" | "embed" => 2,
+        _ => 1,
+    };
+    if files.len() < wanted {
+        return Err(format!(
+            "not enough files given to `lovely {}`\n\n{}",
+            command, USAGE
+        )
+        .into());
+    } else if files.len() > wanted {
+        return Err(format!("unexpected arguments to `lovely {}`\n\n{}", command, USAGE).into());
+    }
+    let path = Path::new(&files[0]);
+
+    match command.as_str() {
+        "assemble" => {
+            let source = std::fs::read_to_string(path)
+                .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+            let ops = asm::assemble(&source)
+                .map_err(|err| format!("{}:{}: {}", path.display(), err.line, err.message))?;
+
+            let out_path = out_path
+                .map(PathBuf::from)
+                .unwrap_or_else(|| path.with_extension("png"));
+            image::draw(&bytecode::encode(&ops))
+                .write_png(&out_path)
+                .map_err(|err| format!("failed to write {}: {}", out_path.display(), err))?;
+        }
+        "run" => {
+            vm::execute(&load(path)?).map_err(|err| format!("{}: {}", path.display(), err))?;
+        }
+        "concat" => {
+            let second = Path::new(&files[1]);
+            let ops = bytecode::concat(&load(path)?, &load(second)?).ok_or_else(|| {
+                format!(
+                    "{} and {} have too many labels between them",
+                    path.display(),
+                    second.display()
+                )
+            })?;
+
+            let out_path = out_path
+                .map(PathBuf::from)
+                .ok_or_else(|| format!("`lovely {}` needs `-o <out.png>`\n\n{}", command, USAGE))?;
+            image::draw(&bytecode::encode(&ops))
+                .write_png(&out_path)
+                .map_err(|err| format!("failed to write {}: {}", out_path.display(), err))?;
+        }
+        "embed" => {
+            let photo_path = Path::new(&files[1]);
+            let bytes = bytecode::encode(&load(path)?);
+            let (mut photo, width, height) = image::load_rgba(photo_path)
+                .map_err(|err| format!("failed to read {}: {}", photo_path.display(), err))?;
+            image::embed(&mut photo, &bytes)
+                .map_err(|err| format!("{}: {}", photo_path.display(), err))?;
+
+            let out_path = out_path
+                .map(PathBuf::from)
+                .ok_or_else(|| format!("`lovely {}` needs `-o <out.png>`\n\n{}", command, USAGE))?;
+            image::save_rgba(&out_path, &photo, width, height)
+                .map_err(|err| format!("failed to write {}: {}", out_path.display(), err))?;
+        }
+        "map" => {
+            let out_path = out_path.map(PathBuf::from).unwrap_or_else(|| {
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                path.with_file_name(format!("{}.map.png", stem))
+            });
+            source_map::draw(&load(path)?)
+                .write_png(&out_path)
+                .map_err(|err| format!("failed to write {}: {}", out_path.display(), err))?;
+        }
+        _ => print!("{}", asm::disassemble(&load(path)?)),
+    }
+
+    Ok(())
+}
+
+fn load(path: &Path) -> Result<Vec<bytecode::Op>, Box<dyn Error>> {
+    let bytes =
+        image::load(path).map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    let ops = bytecode::decode(&bytes).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(ops)
+}
@@ -0,0 +1,178 @@
+use raqote::*;
+
+use crate::{
+    asm::mnemonic,
+    bytecode::{encode, Op},
+    image::{color, frame, VERSION},
+};
+
+/// How wide a tile is in pixels, enough for the longest mnemonic.
+const TILE_WIDTH: f32 = 56.0;
+/// How wide a pixel of the font is in pixels.
+const FONT_SCALE: f32 = 2.0;
+const BORDER_WIDTH: f32 = 2.0;
+
+/// Draws the tiles `image::draw` would for `ops`, but large enough to label.
+/// The first tile of each op says its mnemonic and the rest their byte, the
+/// fields of the header are named, and each op is outlined.
+pub fn draw(ops: &[Op]) -> DrawTarget {
+    let (tiles, tiles_per_row) = frame(&encode(ops));
+
+    // What's written on each tile, and which op or header it belongs to.
+    let mut labels: Vec<String> = tiles.iter().map(|byte| byte.to_string()).collect();
+    let mut groups = vec![None; tiles.len()];
+    for (i, name) in [
+        (0, "lv".to_string()),
+        (2, format!("v{}", VERSION)),
+        (3, "len".to_string()),
+        (7, "crc".to_string()),
+    ] {
+        labels[i] = name;
+    }
+    for group in groups.iter_mut().take(tiles_per_row) {
+        *group = Some(0);
+    }
+    let mut start = tiles_per_row;
+    for (i, op) in ops.iter().enumerate() {
+        let len = encode(std::slice::from_ref(op)).len();
+        labels[start] = mnemonic(op).to_string();
+        for group in &mut groups[start..start + len] {
+            *group = Some(i + 1);
+        }
+        start += len;
+    }
+    // Padding isn't worth reading.
+    for (label, group) in labels.iter_mut().zip(&groups) {
+        if group.is_none() {
+            label.clear();
+        }
+    }
+
+    let size = tiles_per_row as f32 * TILE_WIDTH;
+    let mut dt = DrawTarget::new(size as i32, size as i32);
+    let border = Source::Solid(SolidSource::from_unpremultiplied_argb(0xFF, 0, 0, 0));
+
+    for (i, byte) in tiles.iter().enumerate() {
+        let (column, row) = (i % tiles_per_row, i / tiles_per_row);
+        let (x, y) = (column as f32 * TILE_WIDTH, row as f32 * TILE_WIDTH);
+        let tile = color(*byte);
+        dt.fill_rect(
+            x,
+            y,
+            TILE_WIDTH,
+            TILE_WIDTH,
+            &Source::Solid(tile),
+            &DrawOptions::new(),
+        );
+
+        // Outline the edges where the next tile over belongs to something else.
+        if groups[i].is_some() {
+            let differs = |other: Option<usize>| other.is_none_or(|j| groups[j] != groups[i]);
+            let edges = [
+                (
+                    differs((column > 0).then(|| i - 1)),
+                    (x, y, BORDER_WIDTH, TILE_WIDTH),
+                ),
+                (
+                    differs((column + 1 < tiles_per_row).then(|| i + 1)),
+                    (x + TILE_WIDTH - BORDER_WIDTH, y, BORDER_WIDTH, TILE_WIDTH),
+                ),
+                (
+                    differs(i.checked_sub(tiles_per_row)),
+                    (x, y, TILE_WIDTH, BORDER_WIDTH),
+                ),
+                (
+                    differs(Some(i + tiles_per_row).filter(|j| *j < tiles.len())),
+                    (x, y + TILE_WIDTH - BORDER_WIDTH, TILE_WIDTH, BORDER_WIDTH),
+                ),
+            ];
+            for (_, (x, y, width, height)) in edges.iter().filter(|(differs, _)| *differs) {
+                dt.fill_rect(*x, *y, *width, *height, &border, &DrawOptions::new());
+            }
+        }
+
+        // Dark text on light tiles and the other way around.
+        let luma = 0.299 * tile.r as f32 + 0.587 * tile.g as f32 + 0.114 * tile.b as f32;
+        let ink = if luma > 140.0 { 0x00 } else { 0xFF };
+        draw_text(
+            &mut dt,
+            &labels[i],
+            x + TILE_WIDTH / 2.0,
+            y + TILE_WIDTH / 2.0,
+            SolidSource::from_unpremultiplied_argb(0xFF, ink, ink, ink),
+        );
+    }
+
+    dt
+}
+
+/// Draws `text` centered on `x`, `y`, skipping characters the font doesn't have.
+fn draw_text(dt: &mut DrawTarget, text: &str, x: f32, y: f32, ink: SolidSource) {
+    let advance = 4.0 * FONT_SCALE;
+    let left = x - (text.len() as f32 * advance - FONT_SCALE) / 2.0;
+    let top = y - 5.0 * FONT_SCALE / 2.0;
+
+    for (n, c) in text.chars().enumerate() {
+        let rows = match glyph(c) {
+            Some(rows) => rows,
+            None => continue,
+        };
+        for (row, bits) in rows.iter().enumerate() {
+            for (column, bit) in bits.chars().enumerate() {
+                if bit == '#' {
+                    dt.fill_rect(
+                        left + n as f32 * advance + column as f32 * FONT_SCALE,
+                        top + row as f32 * FONT_SCALE,
+                        FONT_SCALE,
+                        FONT_SCALE,
+                        &Source::Solid(ink),
+                        &DrawOptions::new(),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// A 3x5 pixel font, just enough for mnemonics and numbers.
+fn glyph(c: char) -> Option<[&'static str; 5]> {
+    Some(match c {
+        'a' => [".#.", "#.#", "###", "#.#", "#.#"],
+        'b' => ["##.", "#.#", "##.", "#.#", "##."],
+        'c' => [".##", "#..", "#..", "#..", ".##"],
+        'd' => ["##.", "#.#", "#.#", "#.#", "##."],
+        'e' => ["###", "#..", "##.", "#..", "###"],
+        'f' => ["###", "#..", "##.", "#..", "#.."],
+        'g' => [".##", "#..", "#.#", "#.#", ".##"],
+        'h' => ["#.#", "#.#", "###", "#.#", "#.#"],
+        'i' => ["###", ".#.", ".#.", ".#.", "###"],
+        'j' => ["..#", "..#", "..#", "#.#", ".#."],
+        'k' => ["#.#", "#.#", "##.", "#.#", "#.#"],
+        'l' => ["#..", "#..", "#..", "#..", "###"],
+        'm' => ["#.#", "###", "###", "#.#", "#.#"],
+        'n' => ["##.", "#.#", "#.#", "#.#", "#.#"],
+        'o' => [".#.", "#.#", "#.#", "#.#", ".#."],
+        'p' => ["##.", "#.#", "##.", "#..", "#.."],
+        'q' => [".#.", "#.#", "#.#", "##.", ".##"],
+        'r' => ["##.", "#.#", "##.", "#.#", "#.#"],
+        's' => [".##", "#..", ".#.", "..#", "##."],
+        't' => ["###", ".#.", ".#.", ".#.", ".#."],
+        'u' => ["#.#", "#.#", "#.#", "#.#", "###"],
+        'v' => ["#.#", "#.#", "#.#", "#.#", ".#."],
+        'w' => ["#.#", "#.#", "###", "###", "#.#"],
+        'x' => ["#.#", "#.#", ".#.", "#.#", "#.#"],
+        'y' => ["#.#", "#.#", ".#.", ".#.", ".#."],
+        'z' => ["###", "..#", ".#.", "#..", "###"],
+        '0' => ["###", "#.#", "#.#", "#.#", "###"],
+        '1' => [".#.", "##.", ".#.", ".#.", "###"],
+        '2' => ["##.", "..#", ".#.", "#..", "###"],
+        '3' => ["##.", "..#", ".#.", "..#", "##."],
+        '4' => ["#.#", "#.#", "###", "..#", "..#"],
+        '5' => ["###", "#..", "##.", "..#", "##."],
+        '6' => [".##", "#..", "###", "#.#", "###"],
+        '7' => ["###", "..#", ".#.", ".#.", ".#."],
+        '8' => ["###", "#.#", "###", "#.#", "###"],
+        '9' => ["###", "#.#", "###", "..#", "##."],
+        _ => return None,
+    })
+}
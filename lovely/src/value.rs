@@ -14,6 +14,14 @@ impl Value {
         }
     }
 
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Bool(_) => "bool",
+        }
+    }
+
     pub fn truthy(&self) -> bool {
         match self {
             Value::Number(number) => *number > 0.0,
@@ -1,19 +1,49 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
-use crate::{bytecode::Op, value::Value};
+use crate::{asm::mnemonic, bytecode::Op, value::Value};
 
-struct Executor {
+/// How deeply calls can nest before the program is stopped, so runaway
+/// recursion is an error rather than running out of memory.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+struct Frame {
+    /// Where to carry on after `Ret`.
+    return_to: usize,
+    locals: Vec<Option<Value>>,
+}
+
+impl Frame {
+    fn new(return_to: usize) -> Self {
+        Self {
+            return_to,
+            locals: Vec::new(),
+        }
+    }
+}
+
+struct Executor<'a> {
     stack: Vec<Value>,
-    ops: Vec<Op>,
-    labels: HashMap<u8, usize>,
+    ops: &'a [Op],
+    labels: HashMap<u16, usize>,
+    /// The bottom frame is the top level of the program.
+    frames: Vec<Frame>,
+    globals: Vec<Option<Value>>,
     cursor: usize,
 }
 
-impl Executor {
-    fn new(ops: &[Op]) -> Self {
+/// Puts `value` in `slots[slot]`, growing `slots` if needed.
+fn store(slots: &mut Vec<Option<Value>>, slot: u8, value: Value) {
+    let slot = slot as usize;
+    if slots.len() <= slot {
+        slots.resize(slot + 1, None);
+    }
+    slots[slot] = Some(value);
+}
+
+impl<'a> Executor<'a> {
+    fn new(ops: &'a [Op]) -> Self {
         let mut labels = HashMap::new();
 
-        let ops = ops.to_vec();
         for (i, op) in ops.iter().enumerate() {
             if let Op::Label(id) = op {
                 labels.insert(*id, i);
@@ -22,33 +52,54 @@ impl Executor {
 
         Self {
             stack: Vec::new(),
+            frames: vec![Frame::new(ops.len())],
             ops,
             labels,
+            globals: Vec::new(),
             cursor: 0,
         }
     }
 
-    fn execute_op(&mut self) {
-        match &self.ops[self.cursor] {
+    fn pop(&mut self) -> Result<Value, VmErrorKind> {
+        self.stack.pop().ok_or(VmErrorKind::StackUnderflow)
+    }
+
+    /// The top two values, in the order they were pushed.
+    fn pop2(&mut self) -> Result<(Value, Value), VmErrorKind> {
+        if self.stack.len() < 2 {
+            return Err(VmErrorKind::StackUnderflow);
+        }
+        let second = self.stack.pop().unwrap();
+        let first = self.stack.pop().unwrap();
+        Ok((first, second))
+    }
+
+    fn jump(&mut self, id: u16) -> Result<(), VmErrorKind> {
+        self.cursor = *self
+            .labels
+            .get(&id)
+            .ok_or(VmErrorKind::UndefinedLabel(id))?;
+        Ok(())
+    }
+
+    fn execute_op(&mut self) -> Result<(), VmErrorKind> {
+        let ops = self.ops;
+        match &ops[self.cursor] {
             Op::Nop => {}
             Op::Constant(value) => self.stack.push(value.clone()),
             Op::Pop => {
-                self.stack.pop();
+                self.pop()?;
             }
             Op::Dup => {
-                if let Some(last) = self.stack.last() {
-                    self.stack.push(last.clone())
-                }
+                let last = self.stack.last().ok_or(VmErrorKind::StackUnderflow)?;
+                self.stack.push(last.clone())
             }
             Op::Print => {
-                let value = self.stack.pop();
-                if let Some(value) = value {
-                    println!("{}", value.to_string())
-                }
+                let value = self.pop()?;
+                println!("{}", value.to_string())
             }
             Op::Add => {
-                let second = self.stack.pop().unwrap();
-                let first = self.stack.pop().unwrap();
+                let (first, second) = self.pop2()?;
 
                 let result = match (&first, &second) {
                     (Value::Number(number1), Value::Number(number2)) => {
@@ -60,148 +111,223 @@ impl Executor {
                     (Value::String(string), _) => {
                         Value::String(format!("{}{}", string, second.to_string()))
                     }
-                    _ => panic!(""),
+                    _ => return Err(mismatch(&[&first, &second])),
                 };
 
                 self.stack.push(result);
             }
             Op::Sub => {
-                let second = self.stack.pop().unwrap();
-                let first = self.stack.pop().unwrap();
+                let (first, second) = self.pop2()?;
 
-                let result = match (first, second) {
+                let result = match (&first, &second) {
                     (Value::Number(number1), Value::Number(number2)) => {
                         Value::Number(number1 - number2)
                     }
-                    _ => panic!(""),
+                    _ => return Err(mismatch(&[&first, &second])),
                 };
 
                 self.stack.push(result);
             }
             Op::Mul => {
-                let second = self.stack.pop().unwrap();
-                let first = self.stack.pop().unwrap();
+                let (first, second) = self.pop2()?;
 
-                let result = match (first, second) {
+                let result = match (&first, &second) {
                     (Value::Number(number1), Value::Number(number2)) => {
                         Value::Number(number1 * number2)
                     }
                     (Value::String(string), Value::Number(number)) => {
                         let num_repeated = number.abs().floor() as u64;
-                        let strings = vec![string; num_repeated as usize];
+                        let strings = vec![string.as_str(); num_repeated as usize];
                         Value::String(strings.join(""))
                     }
-                    _ => panic!(""),
+                    _ => return Err(mismatch(&[&first, &second])),
                 };
 
                 self.stack.push(result);
             }
             Op::Div => {
-                let second = self.stack.pop().unwrap();
-                let first = self.stack.pop().unwrap();
+                let (first, second) = self.pop2()?;
 
-                let result = match (first, second) {
+                let result = match (&first, &second) {
+                    (Value::Number(_), Value::Number(number2)) if *number2 == 0.0 => {
+                        return Err(VmErrorKind::DivisionByZero)
+                    }
                     (Value::Number(number1), Value::Number(number2)) => {
                         Value::Number(number1 / number2)
                     }
-                    _ => panic!(""),
+                    _ => return Err(mismatch(&[&first, &second])),
                 };
 
                 self.stack.push(result);
             }
 
             Op::Lt => {
-                let second = self.stack.pop().unwrap();
-                let first = self.stack.pop().unwrap();
+                let (first, second) = self.pop2()?;
 
-                let result = match (first, second) {
+                let result = match (&first, &second) {
                     (Value::Number(number1), Value::Number(number2)) => {
                         Value::Bool(number1 < number2)
                     }
-                    _ => panic!(""),
+                    _ => return Err(mismatch(&[&first, &second])),
                 };
 
                 self.stack.push(result);
             }
             Op::Gt => {
-                let second = self.stack.pop().unwrap();
-                let first = self.stack.pop().unwrap();
+                let (first, second) = self.pop2()?;
 
-                let result = match (first, second) {
+                let result = match (&first, &second) {
                     (Value::Number(number1), Value::Number(number2)) => {
                         Value::Bool(number1 > number2)
                     }
-                    _ => panic!(""),
+                    _ => return Err(mismatch(&[&first, &second])),
                 };
 
                 self.stack.push(result);
             }
             Op::Eq => {
-                let second = self.stack.pop().unwrap();
-                let first = self.stack.pop().unwrap();
+                let (first, second) = self.pop2()?;
                 let result = Value::Bool(first.eq(&second));
                 self.stack.push(result);
             }
-            Op::Not => {
-                if let Some(Value::Bool(boolean)) = self.stack.pop() {
-                    self.stack.push(Value::Bool(!boolean));
-                }
-            }
+            Op::Not => match self.pop()? {
+                Value::Bool(boolean) => self.stack.push(Value::Bool(!boolean)),
+                other => return Err(mismatch(&[&other])),
+            },
             Op::And => {
-                let second = self.stack.pop().unwrap();
-                let first = self.stack.pop().unwrap();
+                let (first, second) = self.pop2()?;
 
-                let result = match (first, second) {
-                    (Value::Bool(bool1), Value::Bool(bool2)) => Value::Bool(bool1 && bool2),
-                    _ => panic!(""),
+                let result = match (&first, &second) {
+                    (Value::Bool(bool1), Value::Bool(bool2)) => Value::Bool(*bool1 && *bool2),
+                    _ => return Err(mismatch(&[&first, &second])),
                 };
 
                 self.stack.push(result);
             }
             Op::Or => {
-                let second = self.stack.pop().unwrap();
-                let first = self.stack.pop().unwrap();
+                let (first, second) = self.pop2()?;
 
-                let result = match (first, second) {
-                    (Value::Bool(bool1), Value::Bool(bool2)) => Value::Bool(bool1 || bool2),
-                    _ => panic!(""),
+                let result = match (&first, &second) {
+                    (Value::Bool(bool1), Value::Bool(bool2)) => Value::Bool(*bool1 || *bool2),
+                    _ => return Err(mismatch(&[&first, &second])),
                 };
 
                 self.stack.push(result);
             }
 
-            Op::Jmp(id) => {
-                if let Some(idx) = self.labels.get(&id) {
-                    self.cursor = *idx;
-                    return;
+            Op::Jmp(id) => return self.jump(*id),
+            Op::JmpIf(id) => {
+                if self.pop()?.truthy() {
+                    return self.jump(*id);
                 }
             }
-            Op::JmpIf(id) => {
-                if let Some(value) = self.stack.pop() {
-                    if let Some(idx) = self.labels.get(&id) {
-                        if value.truthy() {
-                            self.cursor = *idx;
-                            return;
-                        }
-                    }
+            Op::Call(id) => {
+                if self.frames.len() > MAX_CALL_DEPTH {
+                    return Err(VmErrorKind::CallTooDeep);
                 }
+                let return_to = self.cursor + 1;
+                self.jump(*id)?;
+                self.frames.push(Frame::new(return_to));
+                return Ok(());
+            }
+            Op::Ret => {
+                // Returning from the top level ends the program.
+                self.cursor = if self.frames.len() > 1 {
+                    self.frames.pop().unwrap().return_to
+                } else {
+                    self.ops.len()
+                };
+                return Ok(());
+            }
+            Op::Load(slot) => {
+                let frame = self.frames.last().unwrap();
+                match frame.locals.get(*slot as usize) {
+                    Some(Some(value)) => self.stack.push(value.clone()),
+                    _ => return Err(VmErrorKind::UnsetLocal(*slot)),
+                }
+            }
+            Op::Store(slot) => {
+                let value = self.pop()?;
+                store(&mut self.frames.last_mut().unwrap().locals, *slot, value);
+            }
+            Op::GLoad(slot) => match self.globals.get(*slot as usize) {
+                Some(Some(value)) => self.stack.push(value.clone()),
+                _ => return Err(VmErrorKind::UnsetGlobal(*slot)),
+            },
+            Op::GStore(slot) => {
+                let value = self.pop()?;
+                store(&mut self.globals, *slot, value);
             }
             Op::Label(_) => {}
         }
 
         self.cursor += 1;
+        Ok(())
     }
 
-    fn execute(&mut self) -> Option<Value> {
+    fn execute(&mut self) -> Result<Option<Value>, VmError> {
         while self.cursor < self.ops.len() {
-            self.execute_op();
+            let op_index = self.cursor;
+            self.execute_op().map_err(|kind| VmError {
+                op_index,
+                op: mnemonic(&self.ops[op_index]),
+                kind,
+            })?;
         }
 
-        self.stack.pop()
+        Ok(self.stack.pop())
     }
 }
 
-pub fn execute(ops: &[Op]) -> Option<Value> {
+fn mismatch(operands: &[&Value]) -> VmErrorKind {
+    VmErrorKind::TypeMismatch(operands.iter().map(|value| value.type_name()).collect())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmErrorKind {
+    /// The op needed more values than there were on the stack.
+    StackUnderflow,
+    /// The op can't be used on values of these types, in the order they were pushed.
+    TypeMismatch(Vec<&'static str>),
+    UndefinedLabel(u16),
+    DivisionByZero,
+    UnsetLocal(u8),
+    UnsetGlobal(u8),
+    /// More than [`MAX_CALL_DEPTH`] calls haven't returned yet.
+    CallTooDeep,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmError {
+    /// Where the op that failed is in the program.
+    pub op_index: usize,
+    pub op: &'static str,
+    pub kind: VmErrorKind,
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "op {} `{}`: ", self.op_index, self.op)?;
+        match &self.kind {
+            VmErrorKind::StackUnderflow => write!(f, "not enough values on the stack"),
+            VmErrorKind::TypeMismatch(types) => {
+                write!(f, "can't be used on {}", types.join(" and "))
+            }
+            VmErrorKind::UndefinedLabel(id) => write!(f, "there is no label L{}", id),
+            VmErrorKind::DivisionByZero => write!(f, "division by zero"),
+            VmErrorKind::UnsetLocal(slot) => write!(f, "local {} was never stored", slot),
+            VmErrorKind::UnsetGlobal(slot) => write!(f, "global {} was never stored", slot),
+            VmErrorKind::CallTooDeep => {
+                write!(f, "calls nested more than {} deep", MAX_CALL_DEPTH)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+/// Runs `ops` and hands back whatever's left on top of the stack.
+pub fn execute(ops: &[Op]) -> Result<Option<Value>, VmError> {
     let mut executor = Executor::new(ops);
     executor.execute()
 }
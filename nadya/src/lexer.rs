@@ -1,27 +1,14 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, fs, path::Path};
 
 use crate::prelude::*;
 
 /// Lex the file and extract tokens
 pub fn lexer(program: &mut Program) {
-    // Find the entrypoint
-    let exit_point = program
-        .file
-        .iter()
-        .find(|(_, c)| c.syntax == Syntax::Exit)
-        .unwrap()
-        .clone()
-        .0
-        .clone();
-
-    program.exit = exit_point;
-
-    // Find every command connected to the entrypoint
-    let mut commands_queue = vec![exit_point];
+    // Find every command connected to an exit
+    let mut commands_queue: Vec<Point> = program.exits.keys().copied().collect();
 
     // Track the locations we've already visited
-    let mut visited: HashSet<Point> = HashSet::new();
-    visited.insert(exit_point);
+    let mut visited: HashSet<Point> = commands_queue.iter().copied().collect();
 
     while let Some(this_point) = commands_queue.pop() {
         // Look in the four directions around this position
@@ -32,10 +19,27 @@ pub fn lexer(program: &mut Program) {
                 y: this_point.y + direction.1,
             };
 
-            // Get the character at the new position
-            let new_pos_syntax = program.file.get(&new_pos_point).expect(&format!("Remeber that your code needs a buffer of '.' around the outside!")).syntax;
+            // Switches only take variables in from above and below
+            if program.file.get(&this_point).unwrap().syntax == Syntax::Switch && direction.1 == 0 {
+                continue;
+            }
+
+            // Get the character at the new position, where past the end of a
+            // line there's nothing
+            let new_pos_syntax = match program.file.get(&new_pos_point) {
+                Some(place) => place.syntax,
+                None => continue,
+            };
 
-            if new_pos_syntax != Syntax::Floor {
+            if new_pos_syntax == Syntax::Switch {
+                search_switch(
+                    &mut visited,
+                    new_pos_point,
+                    program,
+                    this_point,
+                    &mut commands_queue,
+                );
+            } else if !matches!(new_pos_syntax, Syntax::Floor | Syntax::Label(_)) {
                 search_node(
                     &mut visited,
                     new_pos_point,
@@ -58,7 +62,9 @@ pub fn lexer(program: &mut Program) {
                 | Syntax::Min
                 | Syntax::GreaterThan
                 | Syntax::LessThan
-                | Syntax::Equal => (),
+                | Syntax::Equal
+                | Syntax::Gate
+                | Syntax::Switch => (),
                 Syntax::One
                 | Syntax::Two
                 | Syntax::Three
@@ -75,31 +81,42 @@ pub fn lexer(program: &mut Program) {
                         Spawner::Integer(new_pos_syntax.get_symbol().to_digit(10).unwrap() as i32),
                     );
                 }
-                Syntax::File => {
+                // Only read the file once, however many times the spawner is
+                // found
+                Syntax::File if !program.spawners.contains_key(&new_pos_point) => {
                     // Add this spawner to the program
-
-                    // Load input.txt into an iterator of lines
-                    let numbers =
-                        std::fs::read_to_string(format!("examples/{}/input.txt", program.folder))
-                            .unwrap()
-                            .lines()
-                            .map(|l| l.parse().unwrap())
-                            .collect::<Vec<i32>>();
-
-                    program.spawners.insert(
-                        new_pos_point,
-                        Spawner::File {
-                            data: numbers,
-                            location: 0,
-                        },
-                    );
+                    match read_numbers(&program.input) {
+                        Ok(numbers) => {
+                            program.spawners.insert(
+                                new_pos_point,
+                                Spawner::File {
+                                    data: numbers,
+                                    location: 0,
+                                    once: program.once,
+                                },
+                            );
+                        }
+                        Err(message) => program.problems.push(Problem::at(new_pos_point, message)),
+                    }
+                }
+                Syntax::File => (),
+                Syntax::Literal => {
+                    // Numbers that couldn't be read have already been
+                    // reported
+                    if let Some(number) = program.literals.get(&new_pos_point) {
+                        program
+                            .spawners
+                            .insert(new_pos_point, Spawner::Integer(*number));
+                    }
+                }
+                Syntax::Stdin => {
+                    program.spawners.insert(new_pos_point, Spawner::Stdin);
                 }
                 Syntax::Exit => {
-                    // There is only one exit point, so we can panic if we find
-                    // a different one than the original
-                    // unreachable!();
+                    // Exits are where the search started, so there's nothing
+                    // more to do
                 }
-                Syntax::Floor => (),
+                Syntax::Floor | Syntax::Label(_) => (),
             }
         }
     }
@@ -117,15 +134,69 @@ fn search_node(
         // Update the next position of the found location
         program.file.get_mut(&new_pos_point).unwrap().next = Some(this_point);
 
-        // Update the previous position list of this position
-        program
-            .file
-            .get_mut(&this_point)
-            .unwrap()
-            .prev
-            .push(Some(new_pos_point));
+        add_prev(program, this_point, new_pos_point);
 
         commands_queue.push(new_pos_point);
         visited.insert(new_pos_point);
     }
 }
+
+/// Switches send variables out of their left side, or their right side when
+/// they're 0, so unlike anything else they're found from two places
+fn search_switch(
+    visited: &mut HashSet<Point>,
+    switch_point: Point,
+    program: &mut Program,
+    this_point: Point,
+    commands_queue: &mut Vec<Point>,
+) {
+    let switch = program.file.get_mut(&switch_point).unwrap();
+    match Side::between(this_point, switch_point) {
+        Some(Side::Left) => switch.next = Some(this_point),
+        Some(Side::Right) => switch.otherwise = Some(this_point),
+        // Nothing leaves a switch from above or below
+        _ => return,
+    }
+
+    add_prev(program, this_point, switch_point);
+
+    if visited.insert(switch_point) {
+        commands_queue.push(switch_point);
+    }
+}
+
+/// Update the previous position list of `this_point`, keeping it in the order
+/// operators take their operands
+fn add_prev(program: &mut Program, this_point: Point, new_pos_point: Point) {
+    let prev = &mut program.file.get_mut(&this_point).unwrap().prev;
+    prev.push(Some(new_pos_point));
+    prev.sort_by_key(|point| point.and_then(|point| Side::between(point, this_point)));
+}
+
+/// Load a file with a number on each line, skipping blank lines
+fn read_numbers(path: &Path) -> Result<Vec<i32>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("can't read '{}': {}", path.display(), err))?;
+
+    let numbers = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            line.trim().parse().map_err(|_| {
+                format!(
+                    "line {} of '{}' isn't a number: '{}'",
+                    i + 1,
+                    path.display(),
+                    line
+                )
+            })
+        })
+        .collect::<Result<Vec<i32>, String>>()?;
+
+    if numbers.is_empty() {
+        return Err(format!("there are no numbers in '{}'", path.display()));
+    }
+
+    Ok(numbers)
+}
@@ -1,9 +1,12 @@
 use std::{
-    fs, io,
+    fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process,
     time::{Duration, Instant},
 };
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use crossterm::{
     event,
     event::{DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
@@ -26,41 +29,164 @@ mod place;
 mod program;
 mod simulation;
 mod syntax;
+mod validator;
 
 mod prelude {
-    pub use crate::{lexer::*, parser::*, place::*, program::*, simulation::*, syntax::*};
+    pub use crate::{
+        lexer::*, parser::*, place::*, program::*, simulation::*, syntax::*, validator::*,
+    };
 }
 
-/// Simple program to greet a person
+/// Run nadya programs
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true
+)]
 struct Args {
-    /// Which example to use
-    #[clap(short, long, value_parser, required = true)]
-    example: String,
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Which example to run, the same as `run examples/EXAMPLE/program.nya`
+    #[clap(short, long, value_parser)]
+    example: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a program
+    Run(RunArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct RunArgs {
+    /// The program to run
+    #[clap(value_parser)]
+    path: PathBuf,
+
+    /// Where `F` spawners read numbers from, `input.txt` next to the program
+    /// by default
+    #[clap(short, long, value_parser)]
+    input: Option<PathBuf>,
+
+    /// Stop `F` spawners at the end of the input instead of starting over
+    #[clap(long)]
+    once: bool,
+
+    /// Print outputs to stdout instead of showing the simulation
+    #[clap(long)]
+    headless: bool,
+
+    /// Stop after this many ticks instead of running until nothing's
+    /// happening anymore
+    #[clap(long, value_parser, requires = "headless")]
+    ticks: Option<u64>,
 }
 
 fn main() -> Result<(), io::Error> {
     let args = Args::parse();
 
-    let folder = args.example;
+    let run = match (args.command, args.example) {
+        (Some(Command::Run(run)), _) => run,
+        (None, Some(example)) => RunArgs {
+            path: Path::new("examples").join(example).join("program.nya"),
+            input: None,
+            once: false,
+            headless: false,
+            ticks: None,
+        },
+        (None, None) => {
+            eprintln!("Give a program to run with `nadya run <PATH>`, see `nadya --help`");
+            process::exit(2);
+        }
+    };
 
     // Parse the language
-    // Load the file in the folder provided
-    let contents = fs::read_to_string(format!("examples/{}/program.nya", folder))
-        .expect(&format!("Could not find an example called '{}'!", folder));
+    // Load the file provided
+    let contents = fs::read_to_string(&run.path).unwrap_or_else(|err| {
+        eprintln!("Could not read '{}': {}", run.path.display(), err);
+        process::exit(1);
+    });
+
+    // Numbers come from next to the program unless told otherwise
+    let input = run
+        .input
+        .unwrap_or_else(|| run.path.with_file_name("input.txt"));
 
     // Parse the file
-    let mut program = parse(&contents, folder);
+    let mut program = parse(&contents, input);
+    program.once = run.once;
 
     // Lex the file
     lexer(&mut program);
 
-    setup_terminal(program)?;
+    // Make sure it can run before running it
+    let problems = validate(&program);
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("{}", problem.diagram(&contents));
+        }
+        eprintln!(
+            "Found {} problem{} in '{}'",
+            problems.len(),
+            if problems.len() == 1 { "" } else { "s" },
+            run.path.display()
+        );
+        process::exit(1);
+    }
+
+    // Keys for the UI would end up read as numbers
+    if !run.headless
+        && program
+            .spawners
+            .values()
+            .any(|spawner| *spawner == Spawner::Stdin)
+        && io::stdin().is_terminal()
+    {
+        eprintln!("'I' reads numbers from stdin, so pipe some in or run with --headless");
+        process::exit(1);
+    }
+
+    if run.headless {
+        match run_headless(program, run.ticks) {
+            // Whatever we're piped into stopped reading, which is fine
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {}
+            result => result?,
+        }
+    } else {
+        setup_terminal(program)?;
+    }
 
     Ok(())
 }
 
+/// Run the simulation without a terminal UI, printing each output on its own
+/// line as soon as it leaves the program, until nothing more can happen
+fn run_headless(program: Program, ticks: Option<u64>) -> Result<(), io::Error> {
+    let mut simulation = Simulation::new(program);
+    let mut stdout = io::stdout().lock();
+
+    let mut tick = 0;
+    while ticks.map_or(true, |ticks| tick < ticks) {
+        let changed = simulation.simulate();
+
+        for output in simulation.outputs.drain(..) {
+            writeln!(stdout, "{}", output)?;
+        }
+
+        if !changed {
+            break;
+        }
+
+        tick += 1;
+    }
+
+    stdout.flush()
+}
+
 fn setup_terminal(program: Program) -> Result<(), io::Error> {
     // Set up the terminal
     enable_raw_mode()?;
@@ -70,9 +196,8 @@ fn setup_terminal(program: Program) -> Result<(), io::Error> {
     let mut terminal = Terminal::new(backend)?;
 
     // Set up the app
-    let tick_rate = Duration::from_millis(250);
     let app = App::new(program);
-    let _res = run_app(&mut terminal, app, tick_rate);
+    let _res = run_app(&mut terminal, app);
 
     // Restore terminal
     disable_raw_mode()?;
@@ -87,46 +212,113 @@ fn setup_terminal(program: Program) -> Result<(), io::Error> {
     Ok(())
 }
 
+/// How fast and slow the simulation can be made to go
+const FASTEST_TICK_RATE: Duration = Duration::from_millis(10);
+const SLOWEST_TICK_RATE: Duration = Duration::from_secs(4);
+
 struct App {
     simulation: Simulation,
+    tick_rate: Duration,
+    ticks: u64,
+    paused: bool,
+    /// The tile being looked at
+    cursor: Point,
 }
 
 impl App {
     fn new(program: Program) -> App {
+        let simulation = Simulation::new(program);
+        let cursor = Point {
+            x: simulation.bounds().min_x,
+            y: simulation.bounds().min_y,
+        };
+
         App {
-            simulation: Simulation::new(program),
+            simulation,
+            tick_rate: Duration::from_millis(250),
+            ticks: 0,
+            paused: false,
+            cursor,
         }
     }
 
     fn on_tick(&mut self) {
         // Run a simulation tick
         self.simulation.simulate();
+        self.ticks += 1;
+    }
+
+    /// React to a key, other than quitting
+    fn on_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char(' ') | KeyCode::Char('p') => self.paused = !self.paused,
+            KeyCode::Char('s') => {
+                self.paused = true;
+                self.on_tick();
+            }
+            KeyCode::Char('+') | KeyCode::Char('=') => {
+                self.tick_rate = (self.tick_rate / 2).max(FASTEST_TICK_RATE);
+            }
+            KeyCode::Char('-') => {
+                self.tick_rate = (self.tick_rate * 2).min(SLOWEST_TICK_RATE);
+            }
+            KeyCode::Left | KeyCode::Char('h') => self.move_cursor(-1, 0),
+            KeyCode::Down | KeyCode::Char('j') => self.move_cursor(0, 1),
+            KeyCode::Up | KeyCode::Char('k') => self.move_cursor(0, -1),
+            KeyCode::Right | KeyCode::Char('l') => self.move_cursor(1, 0),
+            _ => {}
+        }
+    }
+
+    /// Move the cursor, keeping it on the program
+    fn move_cursor(&mut self, x: i32, y: i32) {
+        let bounds = self.simulation.bounds();
+        self.cursor = Point {
+            x: (self.cursor.x + x).clamp(bounds.min_x, bounds.max_x),
+            y: (self.cursor.y + y).clamp(bounds.min_y, bounds.max_y),
+        };
+    }
+
+    /// Where a variable came from, if it hasn't merged with another yet
+    fn describe_variable(&self, variable: &Variable) -> String {
+        match variable.spawner {
+            Some(spawner) => format!(
+                "{} from '{}' at ({}, {})",
+                variable.value,
+                self.simulation
+                    .find_place(spawner)
+                    .map_or(' ', |place| place.syntax.get_symbol()),
+                spawner.x,
+                spawner.y
+            ),
+            None => format!("{}", variable.value),
+        }
     }
 }
 
-fn run_app<B: Backend>(
-    terminal: &mut Terminal<B>,
-    mut app: App,
-    tick_rate: Duration,
-) -> io::Result<()> {
+fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<()> {
     let mut last_tick = Instant::now();
     loop {
         terminal.draw(|f| ui(f, &app))?;
 
-        let timeout = tick_rate
+        let timeout = app
+            .tick_rate
             .checked_sub(last_tick.elapsed())
             .unwrap_or_else(|| Duration::from_secs(0));
 
         if crossterm::event::poll(timeout)? {
             if let Event::Key(key) = event::read()? {
-                if let KeyCode::Char('q') = key.code {
-                    return Ok(());
+                match key.code {
+                    KeyCode::Char('q') => return Ok(()),
+                    code => app.on_key(code),
                 }
             }
         }
 
-        if last_tick.elapsed() >= tick_rate {
-            app.on_tick();
+        if last_tick.elapsed() >= app.tick_rate {
+            if !app.paused {
+                app.on_tick();
+            }
             last_tick = Instant::now();
         }
     }
@@ -135,12 +327,51 @@ fn run_app<B: Backend>(
 fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Percentage(80), Constraint::Percentage(20)].as_ref())
+        .constraints(
+            [
+                Constraint::Percentage(80),
+                Constraint::Percentage(20),
+                Constraint::Length(1),
+            ]
+            .as_ref(),
+        )
         .split(f.size());
 
-    let (map_string, x, y) = app.simulation.map_string();
+    let top = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
+        .split(chunks[0]);
+
+    let side = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(4), Constraint::Min(0)].as_ref())
+        .split(top[1]);
 
-    let paragraph = Paragraph::new(map_string)
+    let (map_string, _, _) = app.simulation.map_string();
+
+    // Highlight the tile under the cursor
+    let bounds = app.simulation.bounds();
+    let (cursor_x, cursor_y) = (app.cursor.x - bounds.min_x, app.cursor.y - bounds.min_y);
+    let lines: Vec<Spans> = map_string
+        .lines()
+        .enumerate()
+        .map(|(y, line)| {
+            let chars: Vec<char> = line.chars().collect();
+            match chars.get(cursor_x as usize) {
+                Some(c) if y as i32 == cursor_y => Spans::from(vec![
+                    Span::raw(chars[..cursor_x as usize].iter().collect::<String>()),
+                    Span::styled(
+                        c.to_string(),
+                        Style::default().bg(Color::Blue).fg(Color::White),
+                    ),
+                    Span::raw(chars[cursor_x as usize + 1..].iter().collect::<String>()),
+                ]),
+                _ => Spans::from(line.to_string()),
+            }
+        })
+        .collect();
+
+    let paragraph = Paragraph::new(lines)
         .style(Style::default().bg(Color::White).fg(Color::Black))
         .block(
             Block::default()
@@ -153,7 +384,45 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
         )
         .alignment(Alignment::Center);
 
-    f.render_widget(paragraph, chunks[0]);
+    f.render_widget(paragraph, top[0]);
+
+    // What's under the cursor
+    let symbol = app
+        .simulation
+        .find_place(app.cursor)
+        .map_or(' ', |place| place.syntax.get_symbol());
+    let selected = Paragraph::new(vec![
+        Spans::from(format!(
+            "'{}' at ({}, {})",
+            symbol, app.cursor.x, app.cursor.y
+        )),
+        Spans::from(match app.simulation.find_variable(app.cursor) {
+            Some(variable) => format!("O {}", app.describe_variable(variable)),
+            None => "No variable here".to_string(),
+        }),
+    ])
+    .block(Block::default().borders(Borders::ALL).title("Selected"));
+
+    f.render_widget(selected, side[0]);
+
+    let variables: Vec<ListItem> = app
+        .simulation
+        .live_variables()
+        .iter()
+        .map(|(point, variable)| {
+            ListItem::new(format!(
+                "({}, {}) {}",
+                point.x,
+                point.y,
+                app.describe_variable(variable)
+            ))
+        })
+        .collect();
+
+    let variables_list =
+        List::new(variables).block(Block::default().borders(Borders::ALL).title("Variables"));
+
+    f.render_widget(variables_list, side[1]);
 
     let events: Vec<ListItem> = app
         .simulation
@@ -168,4 +437,13 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
         .start_corner(Corner::TopLeft);
 
     f.render_widget(events_list, chunks[1]);
+
+    let status = Paragraph::new(format!(
+        " {} | tick {} | {}ms per tick | space pause, s step, +/- speed, arrows move, q quit",
+        if app.paused { "Paused" } else { "Running" },
+        app.ticks,
+        app.tick_rate.as_millis()
+    ));
+
+    f.render_widget(status, chunks[2]);
 }
@@ -1,36 +1,105 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf};
 
 use crate::prelude::*;
 
 /// Parse the file into a map of points to characters
-pub fn parse(file: &String, folder: String) -> Program {
+pub fn parse(file: &String, input: PathBuf) -> Program {
     // Make a hashmap of every character
     let mut map: FileMap = HashMap::new();
 
     // Create the bounds of the map
     let mut bounds = Bounds::new();
 
+    // Every exit, and the name of each one that has a name
+    let mut exits = HashMap::new();
+
+    // The number of each bracketed number
+    let mut literals = HashMap::new();
+
+    // Anything that can't be understood
+    let mut problems = Vec::new();
+
     // Iterate over the file
     file.lines().enumerate().for_each(|(i, line)| {
+        // A bracketed exit name or number being read: where it belongs, the
+        // bracket that ends it, and what's been read so far
+        let mut reading: Option<(Point, char, String)> = None;
+
         // Iterate over the line
         line.chars().enumerate().for_each(|(j, c)| {
-            let syntax = c.into();
+            let point = Point {
+                x: j as i32,
+                y: i as i32,
+            };
+            let left = Point {
+                x: point.x - 1,
+                ..point
+            };
+
+            let syntax = match reading.as_mut() {
+                // An exit followed by a bracket has a name, written like
+                // `W(name)`
+                None if c == '(' && exits.contains_key(&left) => {
+                    reading = Some((left, ')', String::new()));
+                    Syntax::Label(c)
+                }
+                // Numbers that aren't a single digit are written in square
+                // brackets, like `[-12]`, and wires connect to the first one
+                None if c == '[' => {
+                    reading = Some((point, ']', String::new()));
+                    Syntax::Literal
+                }
+                Some((_, end, _)) if c == *end => {
+                    let (start, end, text) = reading.take().unwrap();
+                    if end == ')' {
+                        exits.insert(start, Some(text));
+                    } else {
+                        match text.trim().parse() {
+                            Ok(number) => {
+                                literals.insert(start, number);
+                            }
+                            Err(_) => problems
+                                .push(Problem::at(start, format!("'[{}]' isn't a number", text))),
+                        }
+                    }
+                    Syntax::Label(c)
+                }
+                Some((_, _, text)) => {
+                    text.push(c);
+                    Syntax::Label(c)
+                }
+                None => Syntax::try_from(c).unwrap_or_else(|c| {
+                    problems.push(Problem::at(point, format!("'{}' isn't part of nadya", c)));
+                    Syntax::Floor
+                }),
+            };
+
+            if syntax == Syntax::Exit {
+                exits.insert(point, None);
+            }
 
             // Add the character to the hashmap
-            map.insert(
-                Point {
-                    x: j as i32,
-                    y: i as i32,
-                },
-                Place::new(syntax),
-            );
+            map.insert(point, Place::new(syntax));
 
             // Only update the bounds if the character is not floor
             if syntax != Syntax::Floor {
                 bounds.update(j as i32, i as i32);
             }
         });
+
+        if let Some((start, end, _)) = reading {
+            problems.push(Problem::at(
+                start,
+                match end {
+                    ')' => "this exit's name is missing a ')'",
+                    _ => "this number is missing a ']'",
+                },
+            ));
+        }
     });
 
-    Program::new(map, bounds, folder)
+    let mut program = Program::new(map, exits, bounds, input);
+    program.literals = literals;
+    program.problems = problems;
+    program
 }
@@ -6,9 +6,35 @@ pub struct Point {
     pub y: i32,
 }
 
+/// The side of a place that a wire enters from. Operators take their operands
+/// in this order, so `a - b` is written with `a` on the left and `b` on the
+/// right, or `a` above and `b` on the right or below
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Side {
+    Left,
+    Top,
+    Right,
+    Bottom,
+}
+
+impl Side {
+    /// Which side of `to` its neighbour `from` is on
+    pub fn between(from: Point, to: Point) -> Option<Side> {
+        match (from.x - to.x, from.y - to.y) {
+            (-1, 0) => Some(Side::Left),
+            (0, -1) => Some(Side::Top),
+            (1, 0) => Some(Side::Right),
+            (0, 1) => Some(Side::Bottom),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Place {
     pub next: Option<Point>,
+    /// Where a switch sends variables that are 0, while `next` gets the rest
+    pub otherwise: Option<Point>,
     pub prev: Vec<Option<Point>>,
     pub syntax: Syntax,
 }
@@ -17,6 +43,7 @@ impl Place {
     pub fn new(syntax: Syntax) -> Self {
         Self {
             next: None,
+            otherwise: None,
             prev: Vec::new(),
             syntax,
         }
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf};
 
 use crate::prelude::*;
 
@@ -8,19 +8,37 @@ pub type FileMap = HashMap<Point, Place>;
 pub struct Program {
     pub file: FileMap,
     pub spawners: HashMap<Point, Spawner>,
-    pub exit: Point,
+    /// Every exit, along with its name if it has one
+    pub exits: HashMap<Point, Option<String>>,
     pub bounds: Bounds,
-    pub folder: String,
+    /// The number each bracketed number spawns
+    pub literals: HashMap<Point, i32>,
+    /// Where `F` spawners read their numbers from
+    pub input: PathBuf,
+    /// Whether `F` spawners stop at the end of the input instead of starting
+    /// over
+    pub once: bool,
+    /// Problems found while parsing and lexing, which `validate` reports along
+    /// with the rest
+    pub problems: Vec<Problem>,
 }
 
 impl Program {
-    pub fn new(file: FileMap, bounds: Bounds, folder: String) -> Program {
+    pub fn new(
+        file: FileMap,
+        exits: HashMap<Point, Option<String>>,
+        bounds: Bounds,
+        input: PathBuf,
+    ) -> Program {
         Program {
             file,
             spawners: HashMap::new(),
-            exit: Point { x: 0, y: 0 },
+            exits,
             bounds,
-            folder,
+            literals: HashMap::new(),
+            input,
+            once: false,
+            problems: Vec::new(),
         }
     }
 }
@@ -1,11 +1,15 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+};
 
 use crate::prelude::*;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Variable {
     pub value: i32,
-    spawner: Option<Point>,
+    /// Where this variable came from, until it merges with another
+    pub spawner: Option<Point>,
 }
 
 impl Variable {
@@ -25,27 +29,89 @@ impl Simulation {
     pub fn find_variable(&self, position: Point) -> Option<&Variable> {
         self.variables.get(&position)
     }
+
+    pub fn find_place(&self, position: Point) -> Option<&Place> {
+        self.program.file.get(&position)
+    }
+
+    /// Every variable, from the top of the program to the bottom
+    pub fn live_variables(&self) -> Vec<(Point, Variable)> {
+        let mut variables: Vec<(Point, Variable)> = self
+            .variables
+            .iter()
+            .map(|(point, variable)| (*point, *variable))
+            .collect();
+        variables.sort_by_key(|(point, _)| (point.y, point.x));
+        variables
+    }
+
+    pub fn bounds(&self) -> &Bounds {
+        &self.program.bounds
+    }
+
+    /// Where the variable at `position` goes next, which for a switch depends
+    /// on its value
+    fn next_position(&self, position: Point, variable: &Variable) -> Option<Point> {
+        let place = self.program.file.get(&position).unwrap();
+
+        match place.syntax {
+            Syntax::Switch if variable.value == 0 => place.otherwise,
+            _ => place.next,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Spawner {
     Integer(i32),
-    File { data: Vec<i32>, location: usize },
+    File {
+        data: Vec<i32>,
+        location: usize,
+        /// Stop at the end instead of starting over
+        once: bool,
+    },
+    /// A number from each line of stdin, skipping blank lines
+    Stdin,
 }
 
 impl Spawner {
-    pub fn spawn(&mut self) -> i32 {
+    /// The next number, or `None` once there aren't any more
+    pub fn spawn(&mut self) -> Result<Option<i32>, String> {
         match self {
-            Spawner::Integer(i) => *i,
+            Spawner::Integer(i) => Ok(Some(*i)),
             Spawner::File {
                 data,
                 ref mut location,
+                once,
             } => {
+                if *location == data.len() {
+                    if *once {
+                        return Ok(None);
+                    }
+                    *location = 0;
+                }
+
                 let num = data[*location];
                 *location += 1;
-                *location %= data.len();
 
-                num
+                Ok(Some(num))
+            }
+            Spawner::Stdin => {
+                let mut line = String::new();
+                while line.trim().is_empty() {
+                    line.clear();
+                    let read = io::stdin()
+                        .read_line(&mut line)
+                        .map_err(|err| format!("can't read stdin: {}", err))?;
+                    if read == 0 {
+                        return Ok(None);
+                    }
+                }
+
+                line.trim()
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| format!("'{}' from stdin isn't a number", line.trim()))
             }
         }
     }
@@ -66,31 +132,49 @@ pub enum SimulationStateChange {
     },
     Kill {
         variable_point: Point,
+        exit: Point,
+    },
+    Block {
+        variable_point: Point,
     },
     DoNothing,
 }
 
 impl Simulation {
-    pub fn new(mut program: Program) -> Self {
+    pub fn new(program: Program) -> Self {
+        // Spawn from the top of the program to the bottom, so spawners that
+        // share stdin take turns in the same order every time
+        let mut spawners: Vec<Point> = program.spawners.keys().copied().collect();
+        spawners.sort_by_key(|point| (point.y, point.x));
+
+        let mut simulation = Self {
+            variables: HashMap::new(),
+            program,
+            outputs: Vec::new(),
+        };
+
         // Spawn a variable at each entrypoint
-        let variables = program
-            .spawners
-            .iter_mut()
-            .map(|(point, spawner)| {
-                (
-                    *point,
+        for spawner in spawners {
+            simulation.spawn(spawner);
+        }
+
+        simulation
+    }
+
+    /// Spawn a new variable at `spawner`, unless it's run out
+    fn spawn(&mut self, spawner: Point) {
+        match self.program.spawners.get_mut(&spawner).unwrap().spawn() {
+            Ok(Some(value)) => {
+                self.variables.insert(
+                    spawner,
                     Variable {
-                        value: spawner.spawn(),
-                        spawner: Some(*point),
+                        value,
+                        spawner: Some(spawner),
                     },
-                )
-            })
-            .collect();
-
-        Self {
-            variables,
-            program,
-            outputs: Vec::new(),
+                );
+            }
+            Ok(None) => {}
+            Err(message) => self.outputs.push(format!("Error: {}", message)),
         }
     }
 
@@ -105,12 +189,17 @@ impl Simulation {
                     output.push_str("O");
                 } else {
                     let point = Point { x, y };
-                    let place = self.program.file.get(&point).unwrap();
-
-                    // If it's a floor, use a space instead of a period
-                    let symbol = match place.syntax {
+                    let syntax = self
+                        .program
+                        .file
+                        .get(&point)
+                        .map_or(Syntax::Floor, |place| place.syntax);
+
+                    // If it's a floor, or past the end of a line, use a space
+                    // instead of a period
+                    let symbol = match syntax {
                         Syntax::Floor => ' ',
-                        _ => place.syntax.get_symbol(),
+                        _ => syntax.get_symbol(),
                     };
 
                     output.push(symbol);
@@ -126,7 +215,9 @@ impl Simulation {
         )
     }
 
-    pub fn simulate(&mut self) {
+    /// Run a tick, returning whether anything happened, which stops being true
+    /// once every variable is gone or stuck waiting
+    pub fn simulate(&mut self) -> bool {
         // List to queue all changes this update
         let mut changes: Vec<SimulationStateChange> = Vec::new();
 
@@ -134,7 +225,7 @@ impl Simulation {
         let mut already_assessed: HashSet<Point> = HashSet::new();
 
         // Move variables to the next location that their place points to
-        self.variables.iter().for_each(|(point, _variable)| {
+        self.variables.iter().for_each(|(point, variable)| {
             // If we've already assessed this variable, skip it
             if already_assessed.contains(point) {
                 return;
@@ -143,19 +234,17 @@ impl Simulation {
             // Add this point to the list of already assessed points
             already_assessed.insert(*point);
 
-            // If the variable is at the exit, move it back to the start
-            if *point == self.program.exit {
+            // If the variable is at an exit, it's done
+            if self.program.exits.contains_key(point) {
                 changes.push(SimulationStateChange::Kill {
                     variable_point: *point,
+                    exit: *point,
                 });
+                return;
             }
 
             let next_position = self
-                .program
-                .file
-                .get(&point)
-                .unwrap()
-                .next
+                .next_position(*point, variable)
                 .expect("Couldn't figure out where to go next");
 
             // Check the next position
@@ -165,12 +254,18 @@ impl Simulation {
             // each other position has a variable in it
             if next_place.prev.len() > 1 {
                 // See if there's a variable in each position that leads into
-                // this one
-                if next_place
-                    .prev
-                    .iter()
-                    .all(|point| self.find_variable(point.unwrap()).is_some())
-                {
+                // this one, and is actually heading here. One next to a switch
+                // might be going the other way
+                if next_place.prev.iter().all(|point| {
+                    let point = point.unwrap();
+                    self.find_variable(point).is_some_and(|variable| {
+                        self.next_position(point, variable) == Some(next_position)
+                    })
+                }) {
+                    // Spawners of merging variables spawn new ones after the
+                    // merge, so it doesn't remove them again
+                    let mut spawns = Vec::new();
+
                     // Collect all of the variables that will be merging
                     let variables = next_place
                         .prev
@@ -182,7 +277,7 @@ impl Simulation {
 
                             if variable.spawner.is_some() {
                                 // Spawn new variables in their spawner's locations
-                                changes.push(SimulationStateChange::Spawn {
+                                spawns.push(SimulationStateChange::Spawn {
                                     spawner: variable.spawner.unwrap(),
                                 });
                             }
@@ -200,6 +295,7 @@ impl Simulation {
                         variable_points: variables,
                         new_position: next_position,
                     });
+                    changes.append(&mut spawns);
                 } else {
                     // If not, the variable should stay put
                     changes.push(SimulationStateChange::DoNothing);
@@ -208,11 +304,19 @@ impl Simulation {
                 // If there is only one way to get to this place, move the
                 // variable
 
-                // Check if the next point is the exit
-                if next_position == self.program.exit {
+                // Check if the next point is an exit
+                if self.program.exits.contains_key(&next_position) {
                     // If so, kill this variable
                     changes.push(SimulationStateChange::Kill {
                         variable_point: *point,
+                        exit: next_position,
+                    });
+                // If it's a closed gate, it goes no further
+                } else if next_place.syntax == Syntax::Gate
+                    && Syntax::Gate.apply(&[variable.value]).is_none()
+                {
+                    changes.push(SimulationStateChange::Block {
+                        variable_point: *point,
                     });
                 // Otherwise, move it
                 } else {
@@ -224,6 +328,10 @@ impl Simulation {
             }
         });
 
+        let changed = changes
+            .iter()
+            .any(|change| *change != SimulationStateChange::DoNothing);
+
         // Iterate over everything in the changes list
         for change in changes {
             match change {
@@ -246,63 +354,97 @@ impl Simulation {
                     // operation at this location
                     let values = variables
                         .iter()
-                        .map(|point| self.variables.get(point).unwrap().value)
+                        .filter_map(|point| self.variables.get(point))
+                        .map(|variable| variable.value)
                         .collect::<Vec<i32>>();
 
-                    let result: i32 = match self.program.file.get(&new_position).unwrap().syntax {
-                        Syntax::Add => values.iter().sum(),
-                        Syntax::Subtract => todo!(),
-                        Syntax::Multiply => values.iter().product(),
-                        Syntax::Divide => todo!(),
-                        Syntax::Modulo => todo!(),
-                        Syntax::Max => todo!(),
-                        Syntax::Min => todo!(),
-                        Syntax::GreaterThan => todo!(),
-                        Syntax::LessThan => todo!(),
-                        Syntax::Equal => todo!(),
-                        _ => unreachable!(),
-                    };
+                    let syntax = self.program.file.get(&new_position).unwrap().syntax;
 
                     // Remove the old variables from the hashmap
                     variables.iter().for_each(|point| {
                         self.variables.remove(point);
                     });
 
-                    // Create the new variable and add it to the hashmap
-                    self.variables.insert(
-                        new_position,
-                        Variable {
-                            value: result,
-                            spawner: None,
-                        },
-                    );
+                    match syntax.apply(&values) {
+                        // Create the new variable and add it to the hashmap
+                        Some(result) => {
+                            self.variables.insert(
+                                new_position,
+                                Variable {
+                                    value: result,
+                                    spawner: None,
+                                },
+                            );
+                        }
+                        // A closed gate just stops them
+                        None if syntax == Syntax::Gate => {}
+                        // Otherwise the variables are lost, but say why
+                        None => self.outputs.push(format!(
+                            "Error: '{}' has no answer for {:?} at ({}, {})",
+                            syntax.get_symbol(),
+                            values,
+                            new_position.x,
+                            new_position.y
+                        )),
+                    }
                 }
                 SimulationStateChange::Spawn { spawner } => {
                     // Spawn a new variable at the spawner
-                    let new_variable = Variable {
-                        value: self.program.spawners.get_mut(&spawner).unwrap().spawn(),
-                        spawner: Some(spawner),
-                    };
-
-                    self.variables.insert(spawner, new_variable);
+                    self.spawn(spawner);
+                }
+                SimulationStateChange::Block { variable_point } => {
+                    self.variables.remove(&variable_point);
                 }
                 SimulationStateChange::DoNothing => {}
-                SimulationStateChange::Kill { variable_point } => {
-                    // Print the variable's value
-                    // println!(
-                    //     "Output: {}",
-                    //     self.variables.get(&variable_point).unwrap().value
-                    // );
-
-                    self.outputs.push(format!(
-                        "{}",
-                        self.variables.get(&variable_point).unwrap().value
-                    ));
+                SimulationStateChange::Kill {
+                    variable_point,
+                    exit,
+                } => {
+                    // Print the variable's value, along with the exit's name
+                    let value = self.variables.get(&variable_point).unwrap().value;
+                    self.outputs.push(match &self.program.exits[&exit] {
+                        Some(name) => format!("{}: {}", name, value),
+                        None => format!("{}", value),
+                    });
 
                     // Kill the variable
                     self.variables.remove(&variable_point);
                 }
             }
         }
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str, ticks: usize) -> Vec<String> {
+        let mut program = parse(&source.to_string(), "input.txt".into());
+        lexer(&mut program);
+        assert!(validate(&program).is_empty());
+
+        let mut simulation = Simulation::new(program);
+        for _ in 0..ticks {
+            simulation.simulate();
+        }
+        simulation.outputs
+    }
+
+    #[test]
+    fn switch_next_to_an_operator_does_not_merge_into_it() {
+        let source = "\
+.....0.......
+.....|.......
+..W_+?_W(z)..
+....|........
+....5........
+";
+        // Which variable gets looked at first changes from run to run
+        for _ in 0..20 {
+            assert_eq!(run(source, 20), vec!["z: 0".to_string()]);
+        }
     }
 }
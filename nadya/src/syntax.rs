@@ -11,13 +11,22 @@ pub enum Syntax {
     Eight,
     Nine,
     Zero,
+    /// A number written in brackets, which are read with it
+    Literal,
     File,
+    Stdin,
     // Paths
     Exit,
     VerticalConnector,
     HorizontalConnector,
     IntersectingConnector,
     Floor,
+    /// Part of an exit's name, which is read with the exit and otherwise
+    /// treated like floor
+    Label(char),
+    // Control flow
+    Gate,
+    Switch,
     // Operations
     Add,
     Subtract,
@@ -45,13 +54,19 @@ impl Syntax {
             Syntax::Eight => '8',
             Syntax::Nine => '9',
             Syntax::Zero => '0',
+            Syntax::Literal => '[',
             Syntax::File => 'F',
+            Syntax::Stdin => 'I',
             // Paths
             Syntax::Exit => 'W',
             Syntax::VerticalConnector => '|',
             Syntax::HorizontalConnector => '_',
             Syntax::IntersectingConnector => '#',
             Syntax::Floor => '.',
+            Syntax::Label(character) => character,
+            // Control flow
+            Syntax::Gate => '&',
+            Syntax::Switch => '?',
             // Operations
             Syntax::Add => '+',
             Syntax::Subtract => '-',
@@ -66,18 +81,72 @@ impl Syntax {
         }
     }
 
-    // TODO: Make a function that verifies that the syntax is used with the
-    // right number of paramaters
-
     pub fn get_symbol(self) -> char {
         Self::symbol(self)
     }
+
+    /// Whether variables start out from here
+    pub fn is_spawner(self) -> bool {
+        matches!(
+            self,
+            Syntax::One
+                | Syntax::Two
+                | Syntax::Three
+                | Syntax::Four
+                | Syntax::Five
+                | Syntax::Six
+                | Syntax::Seven
+                | Syntax::Eight
+                | Syntax::Nine
+                | Syntax::Zero
+                | Syntax::Literal
+                | Syntax::File
+                | Syntax::Stdin
+        )
+    }
+
+    /// Combine the values of variables merging at an operator, taken in the
+    /// order of the sides they entered from. Comparisons hold for every pair of
+    /// neighbouring operands and give 1 for true and 0 for false. `None` if
+    /// there's no answer, like when dividing by zero.
+    ///
+    /// Gates let their first operand through if the rest aren't 0, or if it
+    /// isn't 0 itself when it's alone, and give `None` otherwise
+    pub fn apply(self, operands: &[i32]) -> Option<i32> {
+        let (first, rest) = operands.split_first()?;
+        let mut pairs = operands.windows(2);
+
+        Some(match self {
+            Syntax::Add => rest.iter().fold(*first, |a, b| a.wrapping_add(*b)),
+            Syntax::Subtract => rest.iter().fold(*first, |a, b| a.wrapping_sub(*b)),
+            Syntax::Multiply => rest.iter().fold(*first, |a, b| a.wrapping_mul(*b)),
+            Syntax::Divide => rest.iter().try_fold(*first, |a, b| a.checked_div(*b))?,
+            Syntax::Modulo => rest
+                .iter()
+                .try_fold(*first, |a, b| a.checked_rem_euclid(*b))?,
+            Syntax::Max => *operands.iter().max()?,
+            Syntax::Min => *operands.iter().min()?,
+            Syntax::GreaterThan => pairs.all(|pair| pair[0] > pair[1]) as i32,
+            Syntax::LessThan => pairs.all(|pair| pair[0] < pair[1]) as i32,
+            Syntax::Equal => pairs.all(|pair| pair[0] == pair[1]) as i32,
+            Syntax::Gate => {
+                let open = match rest {
+                    [] => *first != 0,
+                    conditions => conditions.iter().all(|condition| *condition != 0),
+                };
+                return open.then_some(*first);
+            }
+            _ => return None,
+        })
+    }
 }
 
-/// Convert a character to a syntax
-impl From<char> for Syntax {
-    fn from(character: char) -> Syntax {
-        match character {
+/// Convert a character to a syntax, or give it back if it isn't one
+impl TryFrom<char> for Syntax {
+    type Error = char;
+
+    fn try_from(character: char) -> Result<Syntax, char> {
+        Ok(match character {
             // Entrypoints
             '1' => Syntax::One,
             '2' => Syntax::Two,
@@ -90,12 +159,16 @@ impl From<char> for Syntax {
             '9' => Syntax::Nine,
             '0' => Syntax::Zero,
             'F' => Syntax::File,
+            'I' => Syntax::Stdin,
             // Paths
             'W' => Syntax::Exit,
             '|' => Syntax::VerticalConnector,
             '_' => Syntax::HorizontalConnector,
             '.' => Syntax::Floor,
             '#' => Syntax::IntersectingConnector,
+            // Control flow
+            '&' => Syntax::Gate,
+            '?' => Syntax::Switch,
             // Operations
             '+' => Syntax::Add,
             '-' => Syntax::Subtract,
@@ -108,7 +181,7 @@ impl From<char> for Syntax {
             '<' => Syntax::LessThan,
             '=' => Syntax::Equal,
 
-            _ => panic!("Unknown character"),
-        }
+            _ => return Err(character),
+        })
     }
 }
@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::prelude::*;
+
+/// Something wrong with a program that would stop it from running properly
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Problem {
+    /// Where the problem is, if it's anywhere in particular
+    pub point: Option<Point>,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn at(point: Point, message: impl Into<String>) -> Self {
+        Self {
+            point: Some(point),
+            message: message.into(),
+        }
+    }
+
+    pub fn anywhere(message: impl Into<String>) -> Self {
+        Self {
+            point: None,
+            message: message.into(),
+        }
+    }
+
+    /// The message, followed by the rows of the program around the problem
+    /// with a caret pointing at it
+    pub fn diagram(&self, source: &str) -> String {
+        let point = match self.point {
+            Some(point) => point,
+            None => return format!("error: {}\n", self.message),
+        };
+
+        let mut output = format!("error at ({}, {}): {}\n", point.x, point.y, self.message);
+
+        let lines: Vec<&str> = source.lines().collect();
+        let first = (point.y - 1).max(0) as usize;
+        let last = ((point.y + 1) as usize).min(lines.len().saturating_sub(1));
+        let width = last.to_string().len();
+
+        output.push_str(&format!("{:width$} |\n", "", width = width));
+        for (y, line) in lines.iter().enumerate().take(last + 1).skip(first) {
+            output.push_str(&format!("{:width$} | {}\n", y, line, width = width));
+            if y == point.y as usize {
+                output.push_str(&format!(
+                    "{:width$} | {:x$}^\n",
+                    "",
+                    "",
+                    width = width,
+                    x = point.x as usize
+                ));
+            }
+        }
+
+        output
+    }
+}
+
+/// Check a parsed and lexed program for anything that would go wrong when
+/// it's run, along with any problems found while parsing and lexing
+pub fn validate(program: &Program) -> Vec<Problem> {
+    let mut problems = program.problems.clone();
+
+    check_exits(program, &mut problems);
+    check_connections(program, &mut problems);
+    check_inputs(program, &mut problems);
+
+    // Report them from the top of the program to the bottom
+    problems.sort_by_key(|problem| problem.point.map(|point| (point.y, point.x)));
+    problems
+}
+
+/// There has to be an exit, and exits have to be told apart by their names
+fn check_exits(program: &Program, problems: &mut Vec<Problem>) {
+    if program.exits.is_empty() {
+        problems.push(Problem::anywhere(
+            "there's no exit 'W' for variables to leave through",
+        ));
+    }
+
+    let mut exits: Vec<(&Point, &Option<String>)> = program.exits.iter().collect();
+    exits.sort_by_key(|(point, _)| (point.y, point.x));
+
+    let mut seen: HashMap<&Option<String>, &Point> = HashMap::new();
+    for (point, name) in exits {
+        if let Some(first) = seen.insert(name, point) {
+            let message = match name {
+                Some(name) => format!(
+                    "there's already an exit named '{}' at ({}, {})",
+                    name, first.x, first.y
+                ),
+                None => format!(
+                    "there's already an exit without a name at ({}, {}), so give one of them a name like W(name)",
+                    first.x, first.y
+                ),
+            };
+            problems.push(Problem::at(*point, message));
+            seen.insert(name, first);
+        }
+    }
+}
+
+/// Everything in the program has to lead to an exit
+fn check_connections(program: &Program, problems: &mut Vec<Problem>) {
+    let connected = |point: &Point| is_connected(program, point);
+
+    let mut unconnected: Vec<Point> = program
+        .file
+        .iter()
+        .filter(|(point, place)| {
+            !matches!(place.syntax, Syntax::Floor | Syntax::Label(_)) && !connected(point)
+        })
+        .map(|(point, _)| *point)
+        .collect();
+    unconnected.sort_by_key(|point| (point.y, point.x));
+
+    // Each piece of wire that doesn't go anywhere is reported once, unless it
+    // has spawners on it, which are reported instead
+    let mut seen: HashSet<Point> = HashSet::new();
+    for start in &unconnected {
+        if seen.contains(start) {
+            continue;
+        }
+
+        let mut piece = Vec::new();
+        let mut queue = vec![*start];
+        seen.insert(*start);
+        while let Some(point) = queue.pop() {
+            piece.push(point);
+            for direction in [(0i32, -1i32), (0, 1), (-1, 0), (1, 0)] {
+                let neighbour = Point {
+                    x: point.x + direction.0,
+                    y: point.y + direction.1,
+                };
+                if unconnected.contains(&neighbour) && seen.insert(neighbour) {
+                    queue.push(neighbour);
+                }
+            }
+        }
+
+        let spawners: Vec<&Point> = piece
+            .iter()
+            .filter(|point| program.file[point].syntax.is_spawner())
+            .collect();
+        if spawners.is_empty() {
+            problems.push(Problem::at(*start, "this wire doesn't lead to an exit"));
+        }
+        for point in spawners {
+            problems.push(Problem::at(
+                *point,
+                format!(
+                    "'{}' can't reach an exit",
+                    program.file[point].syntax.get_symbol()
+                ),
+            ));
+        }
+    }
+
+    // Switches have to lead to an exit both ways
+    for (point, place) in &program.file {
+        if place.syntax != Syntax::Switch || !connected(point) {
+            continue;
+        }
+        if place.next.is_none() {
+            problems.push(Problem::at(
+                *point,
+                "'?' needs a wire out of its left side to an exit",
+            ));
+        }
+        if place.otherwise.is_none() {
+            problems.push(Problem::at(
+                *point,
+                "'?' needs a wire out of its right side to an exit",
+            ));
+        }
+    }
+}
+
+/// Everything has to have the right number of wires coming into it, but
+/// anything that doesn't lead to an exit has already been reported
+fn check_inputs(program: &Program, problems: &mut Vec<Problem>) {
+    for (point, place) in &program.file {
+        if !is_connected(program, point) {
+            continue;
+        }
+
+        let inputs = place.prev.len();
+        let symbol = place.syntax.get_symbol();
+
+        let message = match place.syntax {
+            Syntax::Add
+            | Syntax::Subtract
+            | Syntax::Multiply
+            | Syntax::Divide
+            | Syntax::Modulo
+            | Syntax::Max
+            | Syntax::Min
+            | Syntax::GreaterThan
+            | Syntax::LessThan
+            | Syntax::Equal
+                if inputs < 2 =>
+            {
+                format!("'{}' needs at least two inputs, but has {}", symbol, inputs)
+            }
+            Syntax::Gate if inputs == 0 => "'&' needs at least one input, but has none".to_string(),
+            Syntax::Switch if inputs != 1 => format!(
+                "'?' takes one input from above or below, but has {}",
+                inputs
+            ),
+            Syntax::VerticalConnector
+            | Syntax::HorizontalConnector
+            | Syntax::IntersectingConnector
+            | Syntax::Exit
+                if inputs > 1 =>
+            {
+                format!(
+                    "{} wires join at '{}', but they can only join at an operator or a gate",
+                    inputs, symbol
+                )
+            }
+            _ => continue,
+        };
+
+        problems.push(Problem::at(*point, message));
+    }
+}
+
+/// Whether variables at `point` have somewhere to go
+fn is_connected(program: &Program, point: &Point) -> bool {
+    let place = &program.file[point];
+    program.exits.contains_key(point) || place.next.is_some() || place.otherwise.is_some()
+}
@@ -1,8 +1,9 @@
 #[derive(Debug, Clone, Ord, Eq, PartialEq, PartialOrd)]
 pub enum Tok {
     Point(String),  // @define a function (but a point can also be a pointer into the program)
-  //Star,           // *run to the next end, adding all Toks to the program stack without
-                    //  excecuting them. unimplemented for langjam
+    Star,           // *run to the next end, adding all Toks to the program stack without
+                    //  excecuting them. pushes a Block to call later
+    Block(usize),   // a quoted block of code starting at this pc, only ever on the data stack
     End,            // ~pop the program stack and return to the parent function
     Ptr(String),    // #a pointer into the point
     Call,           // !pop & call
@@ -17,7 +18,134 @@ pub enum Point {
     Print,
     Add,
     Dec,
+    Mul,
+    Div,
+    Mod,
     Dupn,
     Spot,
+    Drop,
+    Swap,
+    Over,
+    Rot,
+    Depth,
     Gt,             // >temporary       pc=x>y?pc+1:pc
+    Lt,             // <like Gt         pc=x<y?pc+2:pc
+    Eq,             // =like Gt         pc=x==y?pc+2:pc
+    GtP,            // gt? pushes 1 if x>y, otherwise 0
+    LtP,
+    EqP,
+    Not,
+    Cat,
+    Len,
+    Str,
+    Int,
+    Slice,
+    Read,
+    If,
+    IfElse,
+    While,
+}
+
+impl Point {
+    /// The builtins and the names they're registered under.
+    pub fn builtins() -> Vec<(&'static str, Point)> {
+        use Point::*;
+        vec![
+            ("print", Print),
+            ("+", Add),
+            ("-", Dec),
+            ("*", Mul),
+            ("/", Div),
+            ("%", Mod),
+            ("dupn", Dupn),
+            (".", Spot),
+            ("drop", Drop),
+            ("swap", Swap),
+            ("over", Over),
+            ("rot", Rot),
+            ("depth", Depth),
+            (">", Gt),
+            ("<", Lt),
+            ("=", Eq),
+            ("gt?", GtP),
+            ("lt?", LtP),
+            ("eq?", EqP),
+            ("not", Not),
+            ("cat", Cat),
+            ("len", Len),
+            ("str", Str),
+            ("int", Int),
+            ("slice", Slice),
+            ("read", Read),
+            ("if", If),
+            ("ifelse", IfElse),
+            ("while", While),
+        ]
+    }
+
+    /// How many values a builtin needs on the data stack.
+    pub fn arity(&self) -> usize {
+        use Point::*;
+        match self {
+            User(_) | Depth | Read => 0,
+            Print | Spot | Drop | Not | Len | Str | Int => 1,
+            Add | Dec | Mul | Div | Mod | Dupn | Swap | Over | Gt | Lt | Eq | GtP | LtP | EqP
+            | Cat | If | While => 2,
+            Rot | Slice | IfElse => 3,
+        }
+    }
+
+    /// A stack comment for builtins, rightmost on top.
+    pub fn signature(&self) -> Option<&'static str> {
+        use Point::*;
+        Some(match self {
+            User(_) => return None,
+            Print => "( x -- )",
+            Add => "( a b -- a+b )",
+            Dec => "( a b -- a-b )",
+            Mul => "( a b -- a*b )",
+            Div => "( a b -- a/b )",
+            Mod => "( a b -- a%b )",
+            Dupn => "( x n -- x x… )",
+            Spot => "( … y … x i -- … x … y )",
+            Drop => "( x -- )",
+            Swap => "( a b -- b a )",
+            Over => "( a b -- a b a )",
+            Rot => "( a b c -- b c a )",
+            Depth => "( -- n )",
+            Gt => "( a b -- ) skip 2 if a>b",
+            Lt => "( a b -- ) skip 2 if a<b",
+            Eq => "( a b -- ) skip 2 if a=b",
+            GtP => "( a b -- a>b )",
+            LtP => "( a b -- a<b )",
+            EqP => "( a b -- a=b )",
+            Not => "( b -- !b )",
+            Cat => "( a b -- ab )",
+            Len => "( s -- n )",
+            Str => "( x -- s )",
+            Int => "( s -- i )",
+            Slice => "( s start len -- s )",
+            Read => "( -- line 1 | 0 )",
+            If => "( b q -- )",
+            IfElse => "( b q r -- )",
+            While => "( c q -- )",
+        })
+    }
+
+    /// How many values a builtin takes and leaves, if that's always the same.
+    pub fn effect(&self) -> Option<(usize, usize)> {
+        use Point::*;
+        Some(match self {
+            Print | Drop => (1, 0),
+            Add | Dec | Mul | Div | Mod | GtP | LtP | EqP | Cat => (2, 1),
+            Not | Len | Str | Int => (1, 1),
+            Swap => (2, 2),
+            Over => (2, 3),
+            Rot => (3, 3),
+            Depth => (0, 1),
+            Slice => (3, 1),
+            // These depend on the values, skip code or run blocks.
+            User(_) | Dupn | Spot | Gt | Lt | Eq | Read | If | IfElse | While => return None,
+        })
+    }
 }
@@ -0,0 +1,209 @@
+use std::{collections::VecDeque, fmt};
+
+use anyhow::{anyhow, Result};
+use colored::Colorize;
+use tafokr::{Halt, HybridPc, Machine, MachineDebugFormatLines, MachineVerbosity, Point, Tok};
+
+/// How many steps back `:u` can go.
+const HISTORY_LIMIT: usize = 10_000;
+
+pub enum Breakpoint {
+    /// Stop before this pc.
+    Pc(usize),
+    /// Stop on entering this point.
+    Point(String),
+}
+
+impl Breakpoint {
+    /// `@name` or a pc.
+    pub fn parse(s: &str) -> Result<Self> {
+        match s.strip_prefix('@') {
+            Some(name) if !name.is_empty() => Ok(Breakpoint::Point(name.to_string())),
+            Some(_) => Err(anyhow!("breakpoint needs a point name after @")),
+            None => s
+                .parse()
+                .map(Breakpoint::Pc)
+                .map_err(|_| anyhow!("breakpoint must be @name or a pc, not {s:?}")),
+        }
+    }
+
+    fn hit(&self, m: &Machine) -> bool {
+        // Walking over a definition isn't running it.
+        if !matches!(m.program_stack.last(), Some(HybridPc::Step1 { .. })) {
+            return false;
+        }
+        match self {
+            Breakpoint::Pc(pc) => *pc == m.current_pc,
+            Breakpoint::Point(name) => {
+                matches!(m.points.get(name), Some(Point::User(start)) if *start == m.current_pc)
+            }
+        }
+    }
+}
+
+impl PartialEq for Breakpoint {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Breakpoint::Pc(a), Breakpoint::Pc(b)) => a == b,
+            (Breakpoint::Point(a), Breakpoint::Point(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breakpoint::Pc(pc) => write!(f, "{pc:03}"),
+            Breakpoint::Point(name) => write!(f, "@{name}"),
+        }
+    }
+}
+
+pub enum Resume {
+    /// Until a breakpoint or the end.
+    Go,
+    /// One token.
+    Step,
+    /// One token, running any point or block it enters to completion.
+    StepOver,
+}
+
+/// Everything `:u` puts back. Points defined since stay defined, and
+/// anything printed stays printed.
+struct Snapshot {
+    data: Vec<Tok>,
+    program_stack: Vec<HybridPc>,
+    current_pc: usize,
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    history: VecDeque<Snapshot>,
+    pub paused: bool,
+}
+
+impl Debugger {
+    /// Whether evaluation has to go through [`Debugger::run`].
+    pub fn active(&self) -> bool {
+        self.paused || !self.breakpoints.is_empty()
+    }
+
+    /// Adds `bp`, or removes it if it's already set. True if it was added.
+    pub fn toggle(&mut self, bp: Breakpoint) -> bool {
+        match self.breakpoints.iter().position(|b| *b == bp) {
+            Some(i) => {
+                self.breakpoints.remove(i);
+                false
+            }
+            None => {
+                self.breakpoints.push(bp);
+                true
+            }
+        }
+    }
+
+    /// Stop before the next token without running anything.
+    pub fn pause(&mut self) {
+        self.history.clear();
+        self.paused = true;
+    }
+
+    /// Evaluates until `how` says to stop, recording history as it goes.
+    /// Returns how much data was pushed if the program ran to the end.
+    pub fn run(&mut self, m: &mut Machine, how: Resume) -> Result<Option<usize>> {
+        // A fresh line starts a fresh history; resuming keeps it going.
+        let mut resuming = self.paused;
+        if !resuming {
+            self.history.clear();
+        }
+        self.paused = false;
+
+        let depth = m.program_stack.len();
+        let Debugger {
+            breakpoints,
+            history,
+            ..
+        } = self;
+        let (pushed, halt) = m.eval_until(MachineVerbosity::Trace, |m| {
+            // Never stop before the token we're resuming from.
+            let stop = !std::mem::take(&mut resuming)
+                && (breakpoints.iter().any(|b| b.hit(m))
+                    || match how {
+                        Resume::Go => false,
+                        Resume::Step => true,
+                        Resume::StepOver => m.program_stack.len() <= depth,
+                    });
+            if !stop {
+                history.push_back(Snapshot {
+                    data: m.data.clone(),
+                    program_stack: m.program_stack.clone(),
+                    current_pc: m.current_pc,
+                });
+                if history.len() > HISTORY_LIMIT {
+                    history.pop_front();
+                }
+            }
+            stop
+        })?;
+
+        match halt {
+            Halt::Done => Ok(Some(pushed)),
+            Halt::Paused => {
+                self.paused = true;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Puts the machine back the way it was `n` tokens ago, or as far as
+    /// the history goes. Returns how many tokens were undone.
+    pub fn back(&mut self, m: &mut Machine, n: usize) -> usize {
+        let n = n.min(self.history.len());
+        let mut snapshot = None;
+        for _ in 0..n {
+            snapshot = self.history.pop_back();
+        }
+        if let Some(Snapshot {
+            data,
+            program_stack,
+            current_pc,
+        }) = snapshot
+        {
+            m.data = data;
+            m.program_stack = program_stack;
+            m.current_pc = current_pc;
+            self.paused = true;
+        }
+        n
+    }
+
+    /// Drops whatever was paused and skips to the end of the program.
+    pub fn abort(&mut self, m: &mut Machine) {
+        m.program_stack.truncate(1);
+        m.current_pc = m.program.len();
+        self.history.clear();
+        self.paused = false;
+    }
+
+    pub fn can_go_back(&self) -> bool {
+        !self.history.is_empty()
+    }
+
+    /// Where we're paused, what's next, and the data stack.
+    pub fn describe(&self, m: &Machine) -> Result<String> {
+        let next = match m.program.get(m.current_pc) {
+            Some(tok) => m.debug_format(tok)?,
+            None => "the end".to_string(),
+        };
+        Ok(format!(
+            "{} at {} ({:03}) before {}\n{}",
+            "paused".black().on_yellow(),
+            m.describe_pc(m.current_pc),
+            m.current_pc,
+            next.on_black(),
+            m.debug_formatv(&m.data, MachineDebugFormatLines::Single)?
+        ))
+    }
+}
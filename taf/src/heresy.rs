@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use anyhow::{anyhow, bail, Result};
 use ariadne::{Label, Report, ReportKind, Source};
 use combine::{
@@ -8,13 +10,14 @@ use combine::{
         char::{alpha_num, digit, newline, space, tab},
         combinator::spanned,
     },
-    satisfy, skip_many,
+    position, satisfy, skip_many,
     stream::span::{self, Span},
     token, ParseError, Parser, Stream,
 };
 
 use crate::Tok;
-fn tok<Input>() -> impl Parser<Input, Output = Tok>
+/// A `Tok` along with the positions right before and after it.
+fn tok<Input>() -> impl Parser<Input, Output = (Input::Position, Tok, Input::Position)>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
@@ -30,7 +33,11 @@ where
             token('-'),
             token('?'),
             token('<'),
-            token('>')
+            token('>'),
+            token('='),
+            token('*'),
+            token('/'),
+            token('%')
         ))
     };
     let tstring = || between(token('"'), token('"'), many(satisfy(|s| s != '"'))).map(Tok::String);
@@ -39,39 +46,65 @@ where
     let tpoint = || (token('@'), tid()).map(|(_, id)| Tok::Point(id));
     let tcall = || token('!').map(|_| Tok::Call);
     let tend = || token('~').map(|_| Tok::End);
-    // let tstar = || token('*').map(|_| Tok::Star);
+    let tstar = || token('*').map(|_| Tok::Star);
 
     (
         ws(),
+        position(),
         choice!(
             tpoint(),
             tptr(),
             tcall(),
             tend(),
+            tstar(),
             tint(),
             tstring()
         ),
+        position(),
         ws(),
     )
-        .map(|(_, tok, _)| tok)
+        .map(|(_, start, tok, end, _)| (start, tok, end))
 }
 
-pub fn raw_parser<Input, Q>() -> impl Parser<Input, Output = Vec<Tok>>
+pub fn raw_parser<Input, Q>() -> impl Parser<Input, Output = Vec<(Tok, Span<Q>)>>
 where
     Input: Stream<Token = char, Position = Span<Q>>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     Q: Ord + Clone,
 {
-    (many(spanned(tok())), eof()).map(|(v, _)| v)
+    (many(spanned(tok())), eof()).map(|(v, _): (Vec<_>, _)| {
+        v.into_iter()
+            .map(|(start, tok, end): (Span<Q>, Tok, Span<Q>)| {
+                (
+                    tok,
+                    Span {
+                        start: start.start,
+                        end: end.start,
+                    },
+                )
+            })
+            .collect()
+    })
 }
 
 pub fn parse_str(buf: &str) -> Result<Vec<Tok>> {
+    Ok(parse_spanned(buf)?.into_iter().map(|(tok, _)| tok).collect())
+}
+
+/// Like `parse_str`, but keeps the byte range in `buf` each `Tok` came from.
+pub fn parse_spanned(buf: &str) -> Result<Vec<(Tok, Range<usize>)>> {
     let result = raw_parser().parse(
         // This type took like 20 minutes±5 to write. Not fun.
         span::Stream::<_, easy::Errors<char, &str, span::Span<_>>>::from(buf),
     );
     match result {
-        Ok((toks, _stream)) => Ok(toks),
+        Ok((toks, _stream)) => Ok(toks
+            .into_iter()
+            .map(|(tok, span)| {
+                let span = span.map(|p| p.translate_position(buf));
+                (tok, span.start..span.end)
+            })
+            .collect()),
         Err(raw) => {
             // raw.errors' first element is usually
             // the actual error, and any following elements
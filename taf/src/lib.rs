@@ -4,3 +4,6 @@ mod machine;
 pub use data::*;
 pub use heresy::*;
 pub use machine::*;
+
+/// Definitions every taf program can rely on, evaluated before anything else.
+pub const PRELUDE: &str = include_str!("./prelude.taf");
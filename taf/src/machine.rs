@@ -1,16 +1,28 @@
 use std::collections::HashMap;
 
 use anyhow::{anyhow, bail as anyhow_bail, Result};
+use ariadne::{sources, Color, Label, Report, ReportKind};
 use colored::Colorize;
 use std::ops::Range;
 
-use crate::{Point, Tok};
+use crate::{parse_spanned, Point, Tok};
+
+/// How many frames of a backtrace are shown before the rest are elided.
+const BACKTRACE_FRAMES: usize = 12;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 /// Our machine can do more than just execute instructions linearly.
 pub enum HybridPc {
     Step1 { ret: usize, point: Option<String> },
     WalkToEnd,
+    /// A `#while` loop. Sits below the `Step1` of whichever block is
+    /// running, and decides what comes next once it's done.
+    Loop {
+        ret: usize,
+        cond: usize,
+        body: usize,
+        checking: bool,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -25,11 +37,32 @@ pub enum MachineVerbosity {
     Trace,
 }
 
+/// Why [`Machine::eval_until`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Halt {
+    /// Ran off the end of the program.
+    Done,
+    /// Stopped before `current_pc` because the caller asked to.
+    Paused,
+}
+
+/// Where a `Tok` in the program was written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokSpan {
+    /// Index into `Machine::sources`.
+    pub source: usize,
+    pub range: Range<usize>,
+}
+
 #[derive(Debug)]
 pub struct Machine {
     pub points: HashMap<String, Point>,
     pub data: Vec<Tok>,
     pub program: Vec<Tok>,
+    /// Where each `Tok` in `program` came from, if it was slotted in with its source.
+    pub spans: Vec<Option<TokSpan>>,
+    /// Names and text of the sources slotted in so far.
+    pub sources: Vec<(String, String)>,
     /// A stack of "program counters". You ~ (Tok::End) to pop and ! (Tok::Call) to push
     pub program_stack: Vec<HybridPc>,
     pub current_pc: usize,
@@ -48,18 +81,53 @@ impl Machine {
         None
     }
 
+    /// Leaves the machine somewhere sensible after an error: any running
+    /// `#while` is thrown away along with everything it started, and the
+    /// innermost point call is abandoned.
+    fn unwind(&mut self) {
+        let outermost_loop = self
+            .program_stack
+            .iter()
+            .position(|pc| matches!(pc, HybridPc::Loop { .. }));
+        if let Some(at) = outermost_loop {
+            if let HybridPc::Loop { ret, .. } = self.program_stack[at] {
+                self.current_pc = ret;
+            }
+            self.program_stack.truncate(at.max(1));
+        }
+        if let Some((ret, Some(_point))) = self.last_step1() {
+            self.program_stack.pop().unwrap();
+            self.current_pc = ret;
+        }
+    }
+
+    /// Start executing the code at `start`, returning to the current pc at its `~`.
+    fn enter(&mut self, start: usize, point: Option<String>) -> Result<()> {
+        self.program_stack.push(HybridPc::Step1 {
+            ret: self.current_pc,
+            point,
+        });
+        let max_depth = 100_000;
+        if self.program_stack.len() > max_depth {
+            self.program_stack.pop();
+            anyhow_bail!("maximum stack depth ({max_depth}) reached. cleared.");
+        }
+        // Where we'll go next.
+        self.current_pc = start;
+        Ok(())
+    }
+
     pub fn new() -> Self {
         let mut map = HashMap::new();
-        map.insert("print".to_string(), Point::Print);
-        map.insert("+".to_string(), Point::Add);
-        map.insert("-".to_string(), Point::Dec);
-        map.insert("dupn".to_string(), Point::Dupn);
-        map.insert(".".to_string(), Point::Spot);
-        map.insert(">".to_string(), Point::Gt);
+        for (name, point) in Point::builtins() {
+            map.insert(name.to_string(), point);
+        }
         Machine {
             points: map,
             data: vec![],
             program: vec![],
+            spans: vec![],
+            sources: vec![],
             program_stack: vec![HybridPc::Step1 {
                 ret: 0,
                 point: None,
@@ -70,12 +138,194 @@ impl Machine {
     }
     /// Push a program into the machine. Very skeuomorphic.
     pub fn slot_in(&mut self, mut a_program: Vec<Tok>) {
+        self.spans.resize(self.program.len() + a_program.len(), None);
         self.program.append(&mut a_program);
     }
 
+    /// Parse `src` and push it into the machine, remembering where each `Tok`
+    /// came from so errors can point at it. `name` should be unique.
+    pub fn slot_in_source(&mut self, name: impl Into<String>, src: &str) -> Result<()> {
+        let toks = parse_spanned(src)?;
+        let source = self.sources.len();
+        self.sources.push((name.into(), src.to_string()));
+        for (tok, range) in toks {
+            self.program.push(tok);
+            self.spans.push(Some(TokSpan { source, range }));
+        }
+        Ok(())
+    }
+
+    /// How many values the point `name` takes and leaves. For `@`points this
+    /// is worked out from the body, so it's `None` if anything in there
+    /// depends on the values it's given.
+    pub fn stack_effect(&self, name: &str) -> Option<(usize, usize)> {
+        self.stack_effect_inner(name, &mut vec![])
+    }
+
+    fn stack_effect_inner(&self, name: &str, seen: &mut Vec<String>) -> Option<(usize, usize)> {
+        let mut pc = match self.points.get(name)? {
+            Point::User(start) => *start,
+            builtin => return builtin.effect(),
+        };
+        // Recursion always depends on the values.
+        if seen.iter().any(|s| s == name) {
+            return None;
+        }
+        seen.push(name.to_string());
+
+        let (mut depth, mut lowest) = (0isize, 0isize);
+        loop {
+            let (takes, leaves) = match self.program.get(pc)? {
+                Tok::End => break,
+                Tok::Point(_) => return None,
+                Tok::Star => {
+                    // Skip to past the matching ~, the block is one value.
+                    let mut nested = 1;
+                    while nested > 0 {
+                        pc += 1;
+                        match self.program.get(pc)? {
+                            Tok::Point(_) | Tok::Star => nested += 1,
+                            Tok::End => nested -= 1,
+                            _ => {}
+                        }
+                    }
+                    (0, 1)
+                }
+                // The Ptr before us was counted as a push already.
+                Tok::Call => match (pc.checked_sub(2).map(|p| &self.program[p]), &self.program[pc - 1]) {
+                    (Some(Tok::Int(n)), Tok::Ptr(p)) if matches!(self.points.get(p), Some(Point::Dupn)) => {
+                        (3, *n as usize + 1)
+                    }
+                    (_, Tok::Ptr(p)) => {
+                        let (takes, leaves) = self.stack_effect_inner(p, seen)?;
+                        (takes + 1, leaves)
+                    }
+                    _ => return None,
+                },
+                _ => (0, 1),
+            };
+            depth -= takes as isize;
+            lowest = lowest.min(depth);
+            depth += leaves as isize;
+            pc += 1;
+        }
+
+        seen.pop();
+        Some((-lowest as usize, (depth - lowest) as usize))
+    }
+
+    /// `name:line:col` of the `Tok` at `pc`, if we know where it came from.
+    pub fn describe_pc(&self, pc: usize) -> String {
+        match self.spans.get(pc).cloned().flatten() {
+            Some(span) => {
+                let (name, src) = &self.sources[span.source];
+                let before = &src[..span.range.start];
+                let line = before.matches('\n').count() + 1;
+                let col = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+                format!("{name}:{line}:{col}")
+            }
+            None => format!("pc {pc:03}"),
+        }
+    }
+
+    /// How a frame on the program stack is shown in backtraces.
+    fn describe_frame(&self, point: &str) -> String {
+        match point.strip_prefix('*').and_then(|start| start.parse::<usize>().ok()) {
+            // the block starts right after its *
+            Some(start) => format!("the block at {}", self.describe_pc(start.saturating_sub(1))),
+            None => format!("#{point}"),
+        }
+    }
+
+    /// Renders `msg` as a report pointing at the `Tok` at `pc`, with a
+    /// backtrace of the points and blocks on the program stack.
+    pub fn render_error(&self, pc: usize, msg: &str) -> String {
+        // (name, pc of the call) innermost first. The root frame isn't a call.
+        let frames: Vec<(String, usize)> = self
+            .program_stack
+            .iter()
+            .rev()
+            .filter_map(|frame| match frame {
+                HybridPc::Step1 {
+                    ret,
+                    point: Some(point),
+                } => Some((point.clone(), ret.saturating_sub(1))),
+                _ => None,
+            })
+            .collect();
+
+        let mut backtrace = String::new();
+        for (i, (point, call_pc)) in frames.iter().take(BACKTRACE_FRAMES).enumerate() {
+            backtrace.push_str(&format!(
+                "\n  {i}: in {}, called at {}",
+                self.describe_frame(point),
+                self.describe_pc(*call_pc)
+            ));
+        }
+        if frames.len() > BACKTRACE_FRAMES {
+            backtrace.push_str(&format!(
+                "\n  ... and {} more",
+                frames.len() - BACKTRACE_FRAMES
+            ));
+        }
+
+        let span = match self.spans.get(pc).cloned().flatten() {
+            Some(span) => span,
+            None if frames.is_empty() => return msg.to_string(),
+            None => return format!("{msg}\nbacktrace:{backtrace}"),
+        };
+
+        let id = self.sources[span.source].0.clone();
+        let mut report = Report::build(ReportKind::Error, id.clone(), span.range.start)
+            .with_message(msg)
+            .with_label(
+                Label::new((id, span.range.clone()))
+                    .with_message("while evaluating this")
+                    .with_color(Color::Red),
+            );
+
+        // Recursion calls from the same place over and over, only label it once.
+        let mut labelled = vec![span];
+        for (point, call_pc) in frames.iter().take(BACKTRACE_FRAMES) {
+            if let Some(call_span) = self.spans.get(*call_pc).cloned().flatten() {
+                if !labelled.contains(&call_span) {
+                    report = report.with_label(
+                        Label::new((self.sources[call_span.source].0.clone(), call_span.range.clone()))
+                            .with_message(format!(
+                                "in {}, called from here",
+                                self.describe_frame(point)
+                            ))
+                            .with_color(Color::Yellow),
+                    );
+                    labelled.push(call_span);
+                }
+            }
+        }
+        if !frames.is_empty() {
+            report = report.with_note(format!("backtrace:{backtrace}"));
+        }
+
+        let mut buf = Vec::with_capacity(1024);
+        match report.finish().write(sources(self.sources.clone()), &mut buf) {
+            Ok(()) => String::from_utf8_lossy(&buf).into_owned(),
+            Err(_) => format!("{msg} (at {})", self.describe_pc(pc)),
+        }
+    }
+
     /// Evaluates the program in the machine and returns the amount
     /// of data pushed to the stack by this evaluation.
     pub fn eval(&mut self, verb: MachineVerbosity) -> Result<usize> {
+        self.eval_until(verb, |_| false).map(|(pushed, _)| pushed)
+    }
+
+    /// Like [`Machine::eval`], but asks `pause` before every token whether
+    /// to stop there. A paused machine picks up where it left off on the
+    /// next call.
+    pub fn eval_until(
+        &mut self,
+        verb: MachineVerbosity,
+        mut pause: impl FnMut(&Machine) -> bool,
+    ) -> Result<(usize, Halt)> {
         // Only use to track how much we've pushed.
         // We're allowed to use data on the stack from
         // before.
@@ -89,14 +339,24 @@ impl Machine {
             //     bail!($err);
             // };
             ($fmt:expr, $($arg:tt)*) => {{
-                if let Some((ret, Some(point))) = self.last_step1() {
-                    eprintln!("while calling #{point}:");
-                    self.program_stack.pop().unwrap();
-                    self.current_pc = ret;
-                }
-                anyhow_bail!($fmt, $($arg)*)
+                let report = self.render_error(
+                    self.current_pc.saturating_sub(1),
+                    &format!($fmt, $($arg)*),
+                );
+                self.unwind();
+                anyhow_bail!("{report}")
             }};
         }
+        macro_rules! enter {
+            ($start:expr, $point:expr) => {
+                if let Err(er) = self.enter($start, $point) {
+                    let report =
+                        self.render_error(self.current_pc.saturating_sub(1), &er.to_string());
+                    self.program_stack.truncate(1);
+                    anyhow_bail!("{report}");
+                }
+            };
+        }
         macro_rules! push {
             ($e:expr) => {
                 self.data.push($e);
@@ -110,7 +370,19 @@ impl Machine {
                         pushed = pushed.saturating_sub(1);
                         $ret
                     }
-                    (_, _) => bail!(
+                    #[allow(unreachable_patterns)] // for pop!()
+                    (_, Some(other)) => {
+                        let found = self.debug_format(&other)?;
+                        // leave it for whoever looks at the stack next
+                        self.data.push(other);
+                        bail!(
+                            "expected{} but found {} (tok={})",
+                            $thing,
+                            found.magenta(),
+                            self.debug_format(&self.program[self.current_pc - 1])?
+                        )
+                    }
+                    (_, None) => bail!(
                         "cannot pop{} from an {} (tok={})",
                         $thing,
                         "empty stack".magenta(),
@@ -126,7 +398,30 @@ impl Machine {
             };
         }
 
+        // Like pop!($i) for a value under some that were already popped,
+        // putting those back if it's the wrong type so nothing is lost.
+        macro_rules! pop_under {
+            ($i:ident, $($kept:expr),+) => {{
+                if let Some(other) =
+                    self.data.last().filter(|tok| !matches!(tok, Tok::$i(_)))
+                {
+                    let found = self.debug_format(other)?;
+                    $( self.data.push($kept); )+
+                    bail!(
+                        "expected {} but found {} (tok={})",
+                        stringify!($i).magenta(),
+                        found.magenta(),
+                        self.debug_format(&self.program[self.current_pc - 1])?
+                    )
+                }
+                pop!($i)
+            }};
+        }
+
         while self.current_pc < self.program.len() {
+            if pause(self) {
+                return Ok((pushed, Halt::Paused));
+            }
             let tok = self.program.get(self.current_pc).unwrap();
             let dbg_start = if verb == MachineVerbosity::Trace {
                 format!(
@@ -143,6 +438,7 @@ impl Machine {
                                 }
                             ),
                             HybridPc::WalkToEnd => "————————>".to_string(),
+                            HybridPc::Loop { .. } => "#while".to_string(),
                         }
                     }
                 )
@@ -156,8 +452,13 @@ impl Machine {
                     Tok::End => {
                         self.program_stack.pop();
                     }
+                    // these have their own ~ to walk past
+                    Tok::Point(_) | Tok::Star => {
+                        self.program_stack.push(HybridPc::WalkToEnd);
+                    }
                     _ => {}
                 },
+                HybridPc::Loop { .. } => bail!("a #while loop was left running without a block"),
 
                 HybridPc::Step1 { ret, point: _ } => match tok {
                     // <literals boring=true>
@@ -170,7 +471,14 @@ impl Machine {
                     Tok::Int(i) => {
                         push!(Tok::Int(*i));
                     }
+                    Tok::Block(start) => {
+                        push!(Tok::Block(*start));
+                    }
                     // </literals>
+                    Tok::Star => {
+                        push!(Tok::Block(self.current_pc));
+                        self.program_stack.push(HybridPc::WalkToEnd);
+                    }
                     Tok::Point(st) => {
                         self.points.insert(st.clone(), Point::User(self.current_pc));
                         self.program_stack.push(HybridPc::WalkToEnd);
@@ -184,78 +492,278 @@ impl Machine {
                         }
                         self.program_stack.pop().unwrap();
                         self.current_pc = ret_pc_clone;
-                    }
-                    Tok::Call => {
-                        let ptrptr = &pop!(Ptr);
-                        let ptr = match self.points.get(ptrptr) {
-                            Some(ptr) => ptr,
-                            None => bail!("{ptrptr} is not defined"),
-                        };
-                        match ptr {
-                            Point::User(point) => {
-                                // Where to return to.
-
-                                // TCO could be done here but this isn't a real CPU and we have a looot of memory!
-                                self.program_stack.push(HybridPc::Step1 {
-                                    ret: self.current_pc,
-                                    point: Some(ptrptr.clone()),
-                                });
-                                let max_depth = 100_000;
-                                if self.program_stack.len() > max_depth {
-                                    self.program_stack.clear();
-                                    bail!("maximum stack depth () reached. cleared.");
+
+                        if let Some(HybridPc::Loop {
+                            ret,
+                            cond,
+                            body,
+                            checking,
+                        }) = self.program_stack.last().cloned()
+                        {
+                            let next = if checking {
+                                if pop!(Int) != 0 {
+                                    Some(body)
+                                } else {
+                                    None
                                 }
-                                // Where we'll go next.
-                                self.current_pc = *point;
-                            }
-                            Point::Gt => {
-                                let y = pop!(Int);
-                                let x = pop!(Int);
-                                if x > y {
-                                    self.current_pc += 2; // HACk
+                            } else {
+                                Some(cond)
+                            };
+                            match next {
+                                Some(start) => {
+                                    if let Some(HybridPc::Loop { checking, .. }) =
+                                        self.program_stack.last_mut()
+                                    {
+                                        *checking = !*checking;
+                                    }
+                                    self.current_pc = ret;
+                                    enter!(start, Some(format!("*{start}")));
+                                }
+                                None => {
+                                    self.program_stack.pop();
+                                    self.current_pc = ret;
                                 }
                             }
-                            Point::Print => {
-                                println!("{}", pop!(String));
-                            }
-                            Point::Add => {
-                                let a = pop!(Int);
-                                let b = pop!(Int);
-                                push!(Tok::Int(a + b));
+                        }
+                    }
+                    Tok::Call => match pop!() {
+                        Tok::Block(start) => {
+                            enter!(start, Some(format!("*{start}")));
+                        }
+                        Tok::Ptr(ptrptr) => {
+                            let ptrptr = &ptrptr;
+                            let ptr = match self.points.get(ptrptr) {
+                                Some(ptr) => ptr,
+                                None => bail!("{ptrptr} is not defined"),
+                            };
+                            if self.data.len() < ptr.arity() {
+                                bail!(
+                                    "#{ptrptr} needs {} values on the stack but there {} only {}",
+                                    ptr.arity(),
+                                    if self.data.len() == 1 { "is" } else { "are" },
+                                    self.data.len()
+                                );
                             }
-                            Point::Dec => {
-                                let a = pop!(Int);
-                                let b = pop!(Int);
-                                push!(Tok::Int(b - a));
+                            macro_rules! int_op {
+                                ($f:ident, $what:literal) => {{
+                                    let a = pop!(Int);
+                                    let b = pop_under!(Int, Tok::Int(a));
+                                    match b.$f(a) {
+                                        Some(x) => {
+                                            push!(Tok::Int(x));
+                                        }
+                                        None => bail!("{b} {} {a} {}", ptrptr, $what),
+                                    }
+                                }};
                             }
-                            Point::Dupn => {
-                                let n = pop!(Int);
-                                let x = pop!();
-                                for _i in 0..n {
-                                    push!(x.clone());
+                            match ptr {
+                                Point::User(point) => {
+                                    // TCO could be done here but this isn't a real CPU and we have a looot of memory!
+                                    enter!(*point, Some(ptrptr.clone()));
+                                }
+                                Point::If => {
+                                    let block = pop!(Block);
+                                    if pop!(Int) != 0 {
+                                        enter!(block, Some(format!("*{block}")));
+                                    }
+                                }
+                                Point::IfElse => {
+                                    let otherwise = pop!(Block);
+                                    let then = pop!(Block);
+                                    let block = if pop!(Int) != 0 { then } else { otherwise };
+                                    enter!(block, Some(format!("*{block}")));
+                                }
+                                Point::While => {
+                                    let body = pop!(Block);
+                                    let cond = pop!(Block);
+                                    self.program_stack.push(HybridPc::Loop {
+                                        ret: self.current_pc,
+                                        cond,
+                                        body,
+                                        checking: true,
+                                    });
+                                    enter!(cond, Some(format!("*{cond}")));
+                                }
+                                Point::Gt
+                                | Point::Lt
+                                | Point::Eq
+                                | Point::GtP
+                                | Point::LtP
+                                | Point::EqP => {
+                                    let y = pop!(Int);
+                                    let x = pop_under!(Int, Tok::Int(y));
+                                    let yes = match ptr {
+                                        Point::Gt | Point::GtP => x > y,
+                                        Point::Lt | Point::LtP => x < y,
+                                        _ => x == y,
+                                    };
+                                    match ptr {
+                                        Point::GtP | Point::LtP | Point::EqP => {
+                                            push!(Tok::Int(yes as u64));
+                                        }
+                                        // `#>` and friends came before blocks and skip the
+                                        // `#point!` written after them. They're kept for code
+                                        // written that way, the rest should hand `#gt?` and
+                                        // friends to `#if`. Never skip past the end, or the
+                                        // next code slotted in would lose its start.
+                                        _ if yes => {
+                                            self.current_pc = (self.current_pc + 2).min(self.program.len());
+                                        }
+                                        _ => {}
+                                    }
+                                }
+                                Point::Not => {
+                                    let x = pop!(Int);
+                                    push!(Tok::Int((x == 0) as u64));
+                                }
+                                Point::Print => match pop!() {
+                                    Tok::String(s) => println!("{s}"),
+                                    Tok::Int(i) => println!("{i}"),
+                                    other => {
+                                        let found = self.debug_format(&other)?;
+                                        self.data.push(other);
+                                        bail!("#print can only print strings and ints, not {found}")
+                                    }
+                                },
+                                Point::Add => int_op!(checked_add, "overflows"),
+                                Point::Dec => int_op!(checked_sub, "goes below zero"),
+                                Point::Mul => int_op!(checked_mul, "overflows"),
+                                Point::Div => int_op!(checked_div, "divides by zero"),
+                                Point::Mod => int_op!(checked_rem, "divides by zero"),
+                                Point::Drop => {
+                                    pop!();
+                                }
+                                Point::Swap => {
+                                    let y = pop!();
+                                    let x = pop!();
+                                    push!(y);
+                                    push!(x);
+                                }
+                                Point::Over => {
+                                    let x = self.data[self.data.len() - 2].clone();
+                                    push!(x);
+                                }
+                                Point::Rot => {
+                                    let z = pop!();
+                                    let y = pop!();
+                                    let x = pop!();
+                                    push!(y);
+                                    push!(z);
+                                    push!(x);
+                                }
+                                Point::Depth => {
+                                    let depth = self.data.len() as u64;
+                                    push!(Tok::Int(depth));
+                                }
+                                Point::Cat => {
+                                    let b = pop!(String);
+                                    let a = pop_under!(String, Tok::String(b.clone()));
+                                    push!(Tok::String(a + &b));
+                                }
+                                Point::Len => {
+                                    let s = pop!(String);
+                                    push!(Tok::Int(s.chars().count() as u64));
+                                }
+                                Point::Str => match pop!() {
+                                    Tok::Int(i) => {
+                                        push!(Tok::String(i.to_string()));
+                                    }
+                                    Tok::String(s) => {
+                                        push!(Tok::String(s));
+                                    }
+                                    Tok::Ptr(p) => {
+                                        push!(Tok::String(p));
+                                    }
+                                    other => {
+                                        let found = self.debug_format(&other)?;
+                                        self.data.push(other);
+                                        bail!("#str cannot turn {found} into a string")
+                                    }
+                                },
+                                Point::Int => {
+                                    let s = pop!(String);
+                                    match s.trim().parse() {
+                                        Ok(i) => {
+                                            push!(Tok::Int(i));
+                                        }
+                                        Err(_) => {
+                                            self.data.push(Tok::String(s.clone()));
+                                            bail!("#int cannot parse {:?} as an int", s)
+                                        }
+                                    }
+                                }
+                                Point::Slice => {
+                                    let len = pop!(Int);
+                                    let start = pop_under!(Int, Tok::Int(len));
+                                    let s = pop_under!(String, Tok::Int(start), Tok::Int(len));
+                                    let (start, len) = (start as usize, len as usize);
+                                    let count = s.chars().count();
+                                    if !matches!(start.checked_add(len), Some(end) if end <= count) {
+                                        self.data.push(Tok::String(s));
+                                        self.data.push(Tok::Int(start as u64));
+                                        self.data.push(Tok::Int(len as u64));
+                                        bail!(
+                                            "#slice {start} {len} is out of bounds for a string of length {count}"
+                                        );
+                                    }
+                                    push!(Tok::String(s.chars().skip(start).take(len).collect()));
+                                }
+                                Point::Read => {
+                                    let mut line = String::new();
+                                    let read = std::io::stdin()
+                                        .read_line(&mut line)
+                                        .map_err(|er| anyhow!("#read failed: {er}"))?;
+                                    if read == 0 {
+                                        push!(Tok::Int(0));
+                                    } else {
+                                        let line = line.strip_suffix('\n').unwrap_or(&line);
+                                        let line = line.strip_suffix('\r').unwrap_or(line);
+                                        push!(Tok::String(line.to_string()));
+                                        push!(Tok::Int(1));
+                                    }
+                                }
+                                Point::Dupn => {
+                                    let n = pop!(Int);
+                                    let x = pop!();
+                                    for _i in 0..n {
+                                        push!(x.clone());
+                                    }
+                                    push!(x);
+                                }
+                                // Swap the last element and another one indexed by last-n
+                                Point::Spot => {
+                                    // Call:
+                                    //  1 2 3 1 #.!
+                                    let arg = pop!(Int);
+                                    // i0 1 2
+                                    //  1 2 3
+                                    let idx = match (arg as usize)
+                                        .checked_add(1)
+                                        .and_then(|back| self.data.len().checked_sub(back))
+                                    {
+                                        Some(idx) => idx,
+                                        None => bail!(
+                                            "spot is trying to index too far back ({arg} with {} values on the stack)",
+                                            self.data.len()
+                                        ),
+                                    };
+                                    //    ^
+                                    let a = self.data[idx].clone();
+                                    let b = pop!();
+                                    push!(a);
+                                    *self.data.get_mut(idx).unwrap() = b;
                                 }
-                                push!(x);
-                            }
-                            // Swap the last element and another one indexed by last-n
-                            Point::Spot => {
-                                // Call:
-                                //  1 2 3 1 #.!
-                                let arg = pop!(Int);
-                                // i0 1 2
-                                //  1 2 3
-                                let idx = (self.data.len() - (arg as usize)) - 1;
-                                //    ^
-                                let a = self
-                                    .data
-                                    .get(idx)
-                                    .ok_or_else(|| anyhow!("spot is trying to index too far back"))?
-                                    .clone();
-                                let b = pop!();
-                                push!(a);
-                                *self.data.get_mut(idx).unwrap() = b;
                             }
                         }
-                    }
+                        other => {
+                            let found = self.debug_format(&other)?;
+                            self.data.push(other);
+                            bail!(
+                                "expected a Ptr or a Block to call but found {}",
+                                found.magenta()
+                            )
+                        }
+                    },
                     #[allow(unreachable_patterns)]
                     _ => bail!("{tok:?} is unimplemented"),
                 },
@@ -273,7 +781,7 @@ impl Machine {
 
             // dbg!(&self);
         }
-        Ok(pushed)
+        Ok((pushed, Halt::Done))
     }
 
     pub fn debug_format_inplace(&self, t: &Tok, buf: &mut String) -> Result<()> {
@@ -295,6 +803,8 @@ impl Machine {
             Tok::Point(p) => {
                 buf.push_str(&format!("@{p}"));
             }
+            Tok::Star => buf.push('*'),
+            Tok::Block(start) => buf.push_str(&format!("*{start:03}")),
             #[allow(unreachable_code)]
             _ => {
                 buf.push_str(&format!("{t:#?}"));
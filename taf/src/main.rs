@@ -1,5 +1,5 @@
 #![feature(iter_intersperse)]
-use std::{borrow::Borrow, cell::RefCell, rc::Rc};
+use std::{borrow::Borrow, cell::RefCell, path::PathBuf, rc::Rc};
 
 use clap::{Parser, Subcommand};
 
@@ -9,9 +9,11 @@ use lazy_static::__Deref;
 use rustyline::{
     error::ReadlineError, Cmd, Editor, EventHandler, KeyCode, KeyEvent, Modifiers,
 };
-use tafokr::{parse_str, Machine, MachineVerbosity};
+use debugger::{Breakpoint, Debugger, Resume};
+use tafokr::{parse_str, Machine, MachineVerbosity, Tok, PRELUDE};
 use uxlol::RustylineExt;
 
+mod debugger;
 mod uxlol;
 
 #[derive(Parser, Debug)]
@@ -25,6 +27,50 @@ pub struct Args {
 enum Commands {
     /// Read Eval Print Execute
     Repl {},
+    /// Run a taf program from a file
+    Run {
+        /// Print a trace line for every evaluated token
+        #[clap(long)]
+        trace: bool,
+        /// The program to run
+        file: PathBuf,
+        /// Pushed onto the data stack before the program runs, as ints where possible
+        #[clap(allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+}
+
+/// Loads the prelude into a machine and hides it from traces.
+fn load_prelude(m: &mut Machine) -> Result<()> {
+    m.slot_in_source("prelude.taf", PRELUDE)?;
+    m.eval(MachineVerbosity::Normal)?;
+    assert!(m.data.is_empty(), "prelude should leave stack empty");
+    // blackbox our builtins
+    m.trace_pc_range = m.current_pc + 1..usize::MAX;
+    Ok(())
+}
+
+fn run(file: PathBuf, args: Vec<String>, trace: bool) -> Result<()> {
+    let src = std::fs::read_to_string(&file)
+        .map_err(|er| anyhow!("cannot read {}: {er}", file.display()))?;
+
+    let mut m = Machine::new();
+    load_prelude(&mut m)?;
+    for arg in args {
+        m.data.push(match arg.parse() {
+            Ok(i) => Tok::Int(i),
+            Err(_) => Tok::String(arg),
+        });
+    }
+
+    m.slot_in_source(file.display().to_string(), &src)?;
+    let verb = if trace {
+        MachineVerbosity::Trace
+    } else {
+        MachineVerbosity::Normal
+    };
+    m.eval(verb)?;
+    Ok(())
 }
 
 fn main() -> Result<()> {
@@ -34,7 +80,8 @@ fn main() -> Result<()> {
             let machine = Rc::new(RefCell::new(Machine::new()));
             {
                 let mut m = machine.deref().borrow_mut();
-                m.slot_in(parse_str(include_str!("./repl.taf"))?);
+                m.slot_in_source("prelude.taf", PRELUDE)?;
+                m.slot_in_source("repl.taf", include_str!("./repl.taf"))?;
                 m.eval(MachineVerbosity::Normal)?;
                 assert!(m.data.is_empty(), "repl prelude should leave stack empty");
                 // blackbox our builtins
@@ -48,6 +95,7 @@ fn main() -> Result<()> {
                 KeyEvent(KeyCode::Enter, Modifiers::CTRL),
                 EventHandler::Simple(Cmd::Newline),
             );
+            let mut debugger = Debugger::default();
             loop {
                 let prompt = {
                     let m = machine.deref().borrow();
@@ -58,7 +106,9 @@ fn main() -> Result<()> {
                         data_len_unc.yellow()
                     };
 
-                    let sd_warn = if m.program_stack.len() > 1 {
+                    let sd_warn = if debugger.paused {
+                        "paused".black().on_yellow().to_string()
+                    } else if m.program_stack.len() > 1 {
                         format!(
                             "program stack depth ={}, be careful!",
                             m.program_stack.len()
@@ -81,7 +131,7 @@ fn main() -> Result<()> {
                 match rl.readline(&prompt) {
                     Ok(line) => {
                         rl.add_history_entry(&line);
-                        match repl_eval_line(&mut machine.borrow_mut(), &line) {
+                        match repl_eval_line(&mut machine.borrow_mut(), &mut debugger, &line) {
                             Ok(()) => {}
                             Err(err) => {
                                 eprintln!("{}", err)
@@ -95,15 +145,22 @@ fn main() -> Result<()> {
                 }
             }
             rl.append_history(".tafokr-history")?;
+        }
+        Commands::Run { trace, file, args } => {
+            if let Err(err) = run(file, args, trace) {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
         } // _ => unimplemented!()
     }
     Ok(())
 }
 
-fn repl_eval_line(machine: &mut Machine, mut line: &str) -> Result<()> {
+fn repl_eval_line(machine: &mut Machine, debugger: &mut Debugger, mut line: &str) -> Result<()> {
     enum ReplTask {
         Parse,
         Eval,
+        Debug(Resume),
     }
 
     if line.starts_with(":c") {
@@ -119,35 +176,182 @@ fn repl_eval_line(machine: &mut Machine, mut line: &str) -> Result<()> {
         eprintln!("== data ==\n{}", machine.debug_formatv(&machine.data, tafokr::MachineDebugFormatLines::Multi)?);
         return Ok(());
     }
+    if let Some(arg) = line.strip_prefix(":b") {
+        let arg = arg.trim();
+        if arg.is_empty() {
+            for bp in &debugger.breakpoints {
+                eprintln!("{bp}");
+            }
+        } else {
+            let bp = Breakpoint::parse(arg)?;
+            let name = bp.to_string();
+            let verb = if debugger.toggle(bp) { "set" } else { "cleared" };
+            eprintln!("breakpoint {name} {verb}");
+        }
+        return Ok(());
+    }
+    if let Some(arg) = line.strip_prefix(":u") {
+        let arg = arg.trim();
+        let n = if arg.is_empty() {
+            1
+        } else {
+            arg.parse().map_err(|_| anyhow!("expected a number of steps, not {arg:?}"))?
+        };
+        if debugger.back(machine, n) == 0 {
+            bail!("no history to step back through");
+        }
+        eprintln!("{}", debugger.describe(machine)?);
+        return Ok(());
+    }
+    if line.starts_with(":a") {
+        debugger.abort(machine);
+        return Ok(());
+    }
 
     let task = if line.starts_with(":p") {
         line = &line[2..];
         ReplTask::Parse
+    } else if line.starts_with(":s") {
+        line = &line[2..];
+        ReplTask::Debug(Resume::Step)
+    } else if line.starts_with(":o") {
+        line = &line[2..];
+        ReplTask::Debug(Resume::StepOver)
+    } else if line.starts_with(":g") {
+        line = &line[2..];
+        ReplTask::Debug(Resume::Go)
     } else {
         ReplTask::Eval
     };
 
-    let toks = parse_str(line)?;
-
-    match task {
+    let dat_count = match task {
         ReplTask::Parse => {
+            let toks = parse_str(line)?;
             eprintln!("{toks:#?}");
+            return Ok(());
         }
-        ReplTask::Eval => {
-            machine.slot_in(toks);
-            let dat_count = machine
+        ReplTask::Eval if debugger.paused => {
+            bail!("paused, :g continues, :s/:o step, :u steps back and :a aborts");
+        }
+        ReplTask::Eval if !debugger.active() => {
+            let name = format!("<repl {}>", machine.sources.len());
+            machine.slot_in_source(name, line)?;
+            machine
                 .eval(MachineVerbosity::Trace)
-                .map_err(|er| anyhow!("Eval error:\n\t{er}"))?;
-            if dat_count > 0 {
-                let new = &machine.data[machine.data.len() - dat_count..];
-                let mut buf = String::with_capacity(128);
-                for tok in new {
-                    machine.debug_format_inplace(tok, &mut buf)?;
-                    buf.push('\n');
+                .map_err(|er| anyhow!("Eval error:\n\t{er}"))?
+        }
+        ReplTask::Eval => {
+            let name = format!("<repl {}>", machine.sources.len());
+            machine.slot_in_source(name, line)?;
+            debugger_run(machine, debugger, Resume::Go)?
+        }
+        ReplTask::Debug(how) => {
+            if !line.trim().is_empty() {
+                // Fresh code: :g runs it, :s and :o stop before its first token.
+                if debugger.paused {
+                    bail!("already paused, :a aborts first");
+                }
+                let name = format!("<repl {}>", machine.sources.len());
+                machine.slot_in_source(name, line)?;
+                if let Resume::Go = how {
+                    debugger_run(machine, debugger, how)?
+                } else {
+                    debugger.pause();
+                    eprintln!("{}", debugger.describe(machine)?);
+                    return Ok(());
                 }
-                eprintln!("{buf}");
+            } else if !debugger.paused {
+                bail!("not paused, use :s <code> to step through some code");
+            } else {
+                debugger_run(machine, debugger, how)?
             }
         }
+    };
+
+    if dat_count > 0 {
+        let new = &machine.data[machine.data.len() - dat_count..];
+        let mut buf = String::with_capacity(128);
+        for tok in new {
+            machine.debug_format_inplace(tok, &mut buf)?;
+            buf.push('\n');
+        }
+        eprintln!("{buf}");
     }
     Ok(())
 }
+
+/// Resumes the debugger, reporting where it paused. Returns how much the
+/// evaluation pushed if it ran to the end.
+fn debugger_run(machine: &mut Machine, debugger: &mut Debugger, how: Resume) -> Result<usize> {
+    match debugger.run(machine, how) {
+        Ok(Some(pushed)) => Ok(pushed),
+        Ok(None) => {
+            eprintln!("{}", debugger.describe(machine)?);
+            Ok(0)
+        }
+        Err(er) if debugger.can_go_back() => {
+            Err(anyhow!("Eval error:\n\t{er}\n(:u steps back to before it)"))
+        }
+        Err(er) => Err(anyhow!("Eval error:\n\t{er}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repl() -> (Machine, Debugger) {
+        let mut m = Machine::new();
+        load_prelude(&mut m).unwrap();
+        (m, Debugger::default())
+    }
+
+    #[test]
+    fn failing_while_leaves_repl_usable() {
+        let (mut m, mut dbg) = repl();
+        // the body fails
+        assert!(repl_eval_line(&mut m, &mut dbg, "1 * 1 ~ * #nope! ~ #while!").is_err());
+        assert!(repl_eval_line(&mut m, &mut dbg, "1 2 #+!").is_ok());
+        assert_eq!(m.program_stack.len(), 1);
+        // the condition leaves something that isn't an int
+        m.data.clear();
+        assert!(repl_eval_line(&mut m, &mut dbg, "* \"no\" ~ * ~ #while!").is_err());
+        assert!(repl_eval_line(&mut m, &mut dbg, "1 2 #+!").is_ok());
+        assert_eq!(m.program_stack.len(), 1);
+    }
+
+    #[test]
+    fn failing_int_op_keeps_its_operands() {
+        let (mut m, mut dbg) = repl();
+        assert!(repl_eval_line(&mut m, &mut dbg, "1 \"x\" 2 #+!").is_err());
+        assert_eq!(m.data, vec![Tok::Int(1), Tok::String("x".into()), Tok::Int(2)]);
+        m.data.clear();
+        assert!(repl_eval_line(&mut m, &mut dbg, "1 \"x\" #cat!").is_err());
+        assert_eq!(m.data, vec![Tok::Int(1), Tok::String("x".into())]);
+        m.data.clear();
+        assert!(repl_eval_line(&mut m, &mut dbg, "\"abc\" \"1\" 2 #slice!").is_err());
+        assert_eq!(m.data, vec![Tok::String("abc".into()), Tok::String("1".into()), Tok::Int(2)]);
+        m.data.clear();
+        assert!(repl_eval_line(&mut m, &mut dbg, "\"abc\" 2 2 #slice!").is_err());
+        assert_eq!(m.data, vec![Tok::String("abc".into()), Tok::Int(2), Tok::Int(2)]);
+        m.data.clear();
+        assert!(repl_eval_line(&mut m, &mut dbg, "\"abc\" 18446744073709551615 1 #slice!").is_err());
+        assert!(repl_eval_line(&mut m, &mut dbg, "1 18446744073709551615 #.!").is_err());
+    }
+
+    #[test]
+    fn go_with_code_runs_to_the_end() {
+        let (mut m, mut dbg) = repl();
+        repl_eval_line(&mut m, &mut dbg, ":g 1 2 #+!").unwrap();
+        assert!(!dbg.paused);
+        assert_eq!(m.data, vec![Tok::Int(3)]);
+    }
+
+    #[test]
+    fn skipping_off_the_end_keeps_the_next_line() {
+        let (mut m, mut dbg) = repl();
+        repl_eval_line(&mut m, &mut dbg, "2 1 #>!").unwrap();
+        repl_eval_line(&mut m, &mut dbg, "7").unwrap();
+        assert_eq!(m.data, vec![Tok::Int(7)]);
+    }
+}
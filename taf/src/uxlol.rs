@@ -6,17 +6,70 @@ use lazy_static::__Deref;
 use rustyline::{
     completion::{Completer},
     highlight::Highlighter,
-    hint::Hinter,
-    validate::Validator,
-    Helper,
+    hint::{Hint, Hinter},
+    validate::{ValidationContext, ValidationResult, Validator},
+    Context, Helper,
 };
-use tafokr::{Machine, raw_parser};
+use tafokr::{Machine, Tok, parse_str, raw_parser};
 pub struct RustylineExt {
     weak_machine: Weak<RefCell<Machine>>,
 }
 
 impl RustylineExt {
     pub fn new(weak_machine: Weak<RefCell<Machine>>) -> Self { Self { weak_machine } }
+
+    /// The names of every point starting with `prefix`, sorted.
+    fn points_starting_with(&self, prefix: &str) -> Vec<String> {
+        let rc = self.weak_machine.upgrade().unwrap();
+        let machine = rc.deref().borrow();
+        let mut names: Vec<String> = machine
+            .points
+            .keys()
+            .filter(|name| name.starts_with(prefix))
+            .cloned()
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// A stack comment for the point `name`, see [`Machine::stack_effect`].
+    fn signature(&self, name: &str) -> Option<String> {
+        let rc = self.weak_machine.upgrade().unwrap();
+        let machine = rc.deref().borrow();
+        match machine.points.get(name)?.signature() {
+            Some(sig) => Some(sig.to_string()),
+            None => Some(match machine.stack_effect(name) {
+                Some((takes, leaves)) => format!("( {takes} -- {leaves} )"),
+                None => "( ? )".to_string(),
+            }),
+        }
+    }
+}
+
+/// Where the `#pointer` being typed at `pos` starts (after the `#`) and what's typed of it so far.
+fn ptr_before(line: &str, pos: usize) -> Option<(usize, &str)> {
+    let before = &line[..pos];
+    let start = before
+        .rfind(|c: char| c.is_whitespace() || matches!(c, '!' | '~' | '"' | '#'))
+        .filter(|&i| before[i..].starts_with('#'))?
+        + 1;
+    Some((start, &before[start..]))
+}
+
+/// The rest of a point's name, followed by its stack comment.
+pub struct PointHint {
+    display: String,
+    rest: usize,
+}
+
+impl Hint for PointHint {
+    fn display(&self) -> &str {
+        &self.display
+    }
+    fn completion(&self) -> Option<&str> {
+        // Only the name, the stack comment is just to look at.
+        Some(&self.display[..self.rest]).filter(|rest| !rest.is_empty())
+    }
 }
 
 impl Helper for RustylineExt {}
@@ -42,12 +95,82 @@ impl Highlighter for RustylineExt {
     fn highlight_char(&self, _line: &str, _cursor_pos: usize) -> bool {
         true
     }
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(hint.dimmed().to_string())
+    }
+}
+impl Validator for RustylineExt {
+    /// Keeps reading lines until every `@point` and `*` block is closed with a `~`.
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        Ok(if is_incomplete(ctx.input()) {
+            ValidationResult::Incomplete
+        } else {
+            ValidationResult::Valid(None)
+        })
+    }
+}
+/// Whether `input` leaves a block or string open that the next line could close.
+fn is_incomplete(input: &str) -> bool {
+    // Of the repl commands only these take code.
+    let code = match input.get(..2) {
+        Some(":s" | ":o" | ":g" | ":p") => &input[2..],
+        _ if input.starts_with(':') => return false,
+        _ => input,
+    };
+    let open = match parse_str(code) {
+        Ok(toks) => toks.iter().fold(0isize, |open, tok| match tok {
+            Tok::Point(_) | Tok::Star => open + 1,
+            Tok::End => open - 1,
+            _ => open,
+        }),
+        // An unterminated string might be finished on the next line, anything
+        // else is left for eval to complain about.
+        Err(_) if code.matches('"').count() % 2 == 1 => 1,
+        Err(_) => 0,
+    };
+    open > 0
 }
-impl Validator for RustylineExt {}
 impl Hinter for RustylineExt {
-    type Hint = String;
+    type Hint = PointHint;
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<PointHint> {
+        if pos < line.len() {
+            return None;
+        }
+        let (_, prefix) = ptr_before(line, pos)?;
+        let names = self.points_starting_with(prefix);
+        // An exact match wins, otherwise only hint when there's no doubt.
+        let name = match names.iter().find(|name| *name == prefix) {
+            Some(name) => name,
+            None if names.len() == 1 => &names[0],
+            None => return None,
+        };
+        let rest = &name[prefix.len()..];
+        Some(PointHint {
+            display: format!("{rest} {}", self.signature(name)?),
+            rest: rest.len(),
+        })
+    }
 }
 impl Completer for RustylineExt {
     type Candidate = String;
-    // TODO
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(match ptr_before(line, pos) {
+            Some((start, prefix)) => (start, self.points_starting_with(prefix)),
+            None => (pos, vec![]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_incomplete;
+
+    #[test]
+    fn code_after_commands_can_span_lines() {
+        for cmd in ["", ":s", ":o", ":g", ":p"] {
+            assert!(is_incomplete(&format!("{cmd} 1 * 2")), "{cmd:?}");
+            assert!(!is_incomplete(&format!("{cmd} 1 * 2 ~")), "{cmd:?}");
+        }
+        assert!(!is_incomplete(":b @fib"));
+    }
 }
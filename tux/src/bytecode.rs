@@ -0,0 +1,427 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::compiler::{Instructions, Op, Operand};
+use crate::evaluator;
+use crate::utils::*;
+
+pub const EXTENSION: &str = "tuxc";
+
+const MAGIC: &[u8; 4] = b"TUXC";
+const VERSION: u16 = 3;
+
+// Layout of a `.tuxc` file, all integers are little endian:
+//
+//   magic         4 bytes  "TUXC"
+//   version       u16
+//   label count   u16
+//   labels        label count times:
+//                   offset  u32
+//                   length  u8
+//                   name    length bytes of UTF-8
+//   file count    u16
+//   files         file count times, paths of `%include`d files:
+//                   length  u16
+//                   path    length bytes of UTF-8
+//   line count    u32
+//   lines         line count times:
+//                   offset  u32
+//                   file    u16, 0 for the main file or 1 + index into files
+//                   line    u32
+//                   column  u32
+//   code length   u32
+//   code          code length bytes
+
+pub struct Program {
+    /// Label names and the offsets they point to, sorted by offset.
+    pub labels: Vec<(String, usize)>,
+    /// Source locations of the instructions starting at each offset, sorted by offset.
+    pub locations: Vec<(usize, CodeLocation)>,
+    pub instructions: Instructions,
+}
+
+impl Program {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+
+        let label_count: u16 = self
+            .labels
+            .len()
+            .try_into()
+            .map_err(|_| bytecode_error("Too many labels for the bytecode format."))?;
+        bytes.extend_from_slice(&label_count.to_le_bytes());
+
+        for (name, offset) in &self.labels {
+            let name_len: u8 = name
+                .len()
+                .try_into()
+                .map_err(|_| bytecode_error(format!("Label name `{}` is too long.", name)))?;
+            bytes.extend_from_slice(&(*offset as u32).to_le_bytes());
+            bytes.push(name_len);
+            bytes.extend_from_slice(name.as_bytes());
+        }
+
+        let mut files: Vec<&str> = self
+            .locations
+            .iter()
+            .filter_map(|(_, location)| location.file)
+            .collect();
+        files.sort_unstable();
+        files.dedup();
+
+        let file_count: u16 = files
+            .len()
+            .try_into()
+            .map_err(|_| bytecode_error("Too many included files for the bytecode format."))?;
+        bytes.extend_from_slice(&file_count.to_le_bytes());
+        for file in &files {
+            let path_len: u16 = file
+                .len()
+                .try_into()
+                .map_err(|_| bytecode_error(format!("File path `{}` is too long.", file)))?;
+            bytes.extend_from_slice(&path_len.to_le_bytes());
+            bytes.extend_from_slice(file.as_bytes());
+        }
+
+        bytes.extend_from_slice(&(self.locations.len() as u32).to_le_bytes());
+        for (offset, location) in &self.locations {
+            let file = match location.file {
+                Some(file) => files.binary_search(&file).map_or(0, |idx| idx + 1),
+                None => 0,
+            };
+            bytes.extend_from_slice(&(*offset as u32).to_le_bytes());
+            bytes.extend_from_slice(&(file as u16).to_le_bytes());
+            bytes.extend_from_slice(&(location.line as u32).to_le_bytes());
+            bytes.extend_from_slice(&(location.col as u32).to_le_bytes());
+        }
+
+        bytes.extend_from_slice(&(self.instructions.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.instructions);
+
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut r = Reader { bytes, index: 0 };
+
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err(bytecode_error("Not a tux bytecode file."));
+        }
+
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(bytecode_error(format!(
+                "Unsupported bytecode version {} (expected {}).",
+                version, VERSION
+            )));
+        }
+
+        let label_count = r.u16()?;
+        let mut labels = Vec::with_capacity(label_count as usize);
+        for _ in 0..label_count {
+            let offset = r.u32()? as usize;
+            let name_len = r.u8()? as usize;
+            let name = std::str::from_utf8(r.take(name_len)?)
+                .map_err(|_| bytecode_error("Label name is not valid UTF-8."))?;
+            labels.push((name.to_string(), offset));
+        }
+
+        let file_count = r.u16()?;
+        let mut files: Vec<&'static str> = Vec::with_capacity(file_count as usize);
+        for _ in 0..file_count {
+            let path_len = r.u16()? as usize;
+            let path = std::str::from_utf8(r.take(path_len)?)
+                .map_err(|_| bytecode_error("File path is not valid UTF-8."))?;
+            files.push(Box::leak(path.to_string().into_boxed_str()));
+        }
+
+        let line_count = r.u32()?;
+        let mut locations = Vec::new();
+        for _ in 0..line_count {
+            let offset = r.u32()? as usize;
+            let file = match r.u16()? as usize {
+                0 => None,
+                idx => Some(
+                    *files
+                        .get(idx - 1)
+                        .ok_or_else(|| bytecode_error("Line table refers to an unknown file."))?,
+                ),
+            };
+            let line = r.u32()? as usize;
+            let col = r.u32()? as usize;
+            locations.push((offset, CodeLocation::in_file(line, col, file)));
+        }
+
+        let code_len = r.u32()? as usize;
+        let instructions = r.take(code_len)?.to_vec();
+
+        if r.index != bytes.len() {
+            return Err(bytecode_error("Trailing bytes after the end of the code."));
+        }
+
+        Ok(Self {
+            labels,
+            locations,
+            instructions,
+        })
+    }
+
+    /// Maps a bytecode offset back to the source location of the instruction
+    /// containing it. Yields an unknown (0, 0) location if there is no debug info.
+    pub fn location_of(&self, offset: usize) -> CodeLocation {
+        let idx = self
+            .locations
+            .partition_point(|(start, _)| *start <= offset);
+        if idx == 0 {
+            CodeLocation::new(0, 0)
+        } else {
+            self.locations[idx - 1].1
+        }
+    }
+
+    /// Labels at `offset` that can be written back as tux source. Labels from
+    /// macro expansions contain a `.` and are left out.
+    fn labels_at(&self, offset: usize) -> impl Iterator<Item = &str> {
+        self.labels
+            .iter()
+            .filter(move |(name, dst)| *dst == offset && !name.contains('.'))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    index: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let slice = self
+            .bytes
+            .get(self.index..self.index + n)
+            .ok_or_else(|| bytecode_error("Unexpected end of bytecode file."))?;
+        self.index += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Arg {
+    Register(u8),
+    Int(i16),
+    /// Absolute jump destination. May be out of range in malformed bytecode.
+    Target(isize),
+}
+
+#[derive(Debug)]
+pub struct Instruction {
+    pub offset: usize,
+    pub size: usize,
+    pub op: Op,
+    pub args: Vec<Arg>,
+}
+
+pub fn decode_instruction(code: &[u8], offset: usize) -> Result<Instruction> {
+    let byte = |i: usize| {
+        code.get(i).copied().ok_or_else(|| {
+            bytecode_error(format!("Truncated instruction at offset {:#06x}.", offset))
+        })
+    };
+
+    let op: Op = byte(offset)?
+        .try_into()
+        .map_err(|err| bytecode_error(format!("{} at offset {:#06x}.", err, offset)))?;
+
+    let mut index = offset + 1;
+    let mut args = Vec::with_capacity(op.operands().len());
+    for operand in op.operands() {
+        match operand {
+            Operand::Register => {
+                args.push(Arg::Register(byte(index)?));
+                index += 1;
+            }
+            Operand::Value => match byte(index)? {
+                0 => {
+                    let value = i16::from_le_bytes([byte(index + 1)?, byte(index + 2)?]);
+                    args.push(Arg::Int(value));
+                    index += 3;
+                }
+                1 => {
+                    args.push(Arg::Register(byte(index + 1)?));
+                    index += 2;
+                }
+                tag => {
+                    return Err(bytecode_error(format!(
+                        "Invalid value tag {} at offset {:#06x}.",
+                        tag, index
+                    )))
+                }
+            },
+            Operand::Jump => {
+                let jump = i16::from_le_bytes([byte(index)?, byte(index + 1)?]);
+                args.push(Arg::Target(index as isize + jump as isize));
+                index += 2;
+            }
+        }
+    }
+
+    Ok(Instruction {
+        offset,
+        size: index - offset,
+        op,
+        args,
+    })
+}
+
+pub fn decode(code: &[u8]) -> Result<Vec<Instruction>> {
+    let mut instructions = vec![];
+    let mut offset = 0;
+    while offset < code.len() {
+        let inst = decode_instruction(code, offset)?;
+        offset += inst.size;
+        instructions.push(inst);
+    }
+    Ok(instructions)
+}
+
+/// Checks that `program` can be executed without running off the rails: every
+/// op is known, every register exists and every jump lands on an instruction.
+pub fn verify(program: &Program) -> Result<()> {
+    let code = &program.instructions;
+    let instructions = decode(code)?;
+
+    let mut starts: HashSet<usize> = instructions.iter().map(|i| i.offset).collect();
+    starts.insert(code.len());
+
+    for inst in &instructions {
+        if inst.op == Op::NoOp {
+            return Err(bytecode_error(format!(
+                "`noop` at offset {:#06x} cannot be executed.",
+                inst.offset
+            )));
+        }
+
+        for arg in &inst.args {
+            match *arg {
+                Arg::Register(reg) if reg as usize >= evaluator::NUM_REGISTERS => {
+                    return Err(Error::new(
+                        program.location_of(inst.offset),
+                        format!(
+                            "Invalid register r{} in `{}` at offset {:#06x}; registers are r0-r15.",
+                            reg,
+                            inst.op.mnemonic(),
+                            inst.offset
+                        ),
+                    ))
+                }
+                Arg::Target(dst) if dst < 0 || !starts.contains(&(dst as usize)) => {
+                    return Err(bytecode_error(format!(
+                        "`{}` at offset {:#06x} jumps to {:#06x}, which is not the start of an instruction.",
+                        inst.op.mnemonic(),
+                        inst.offset,
+                        dst
+                    )))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    for (name, offset) in &program.labels {
+        if !starts.contains(offset) {
+            return Err(bytecode_error(format!(
+                "Label `{}` points to {:#06x}, which is not the start of an instruction.",
+                name, offset
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Turns `program` back into tux source. Jump targets without a label in the
+/// program's label table get a generated name.
+pub fn disassemble(program: &Program) -> Result<String> {
+    let instructions = decode(&program.instructions)?;
+
+    let mut generated = vec![];
+    for inst in &instructions {
+        for arg in &inst.args {
+            if let Arg::Target(dst) = *arg {
+                if dst >= 0 && program.labels_at(dst as usize).next().is_none() {
+                    generated.push((format!("L{:04x}", dst), dst as usize));
+                }
+            }
+        }
+    }
+    generated.sort();
+    generated.dedup();
+
+    let label_name = |dst: isize| -> String {
+        if dst < 0 {
+            return format!("{}", dst);
+        }
+        let dst = dst as usize;
+        program
+            .labels_at(dst)
+            .next()
+            .map(str::to_string)
+            .or_else(|| {
+                generated
+                    .iter()
+                    .find(|(_, offset)| *offset == dst)
+                    .map(|(name, _)| name.clone())
+            })
+            .unwrap_or_default()
+    };
+
+    let write_labels = |out: &mut String, offset: usize| {
+        for name in program.labels_at(offset) {
+            let _ = writeln!(out, "{}:", name);
+        }
+        for (name, _) in generated.iter().filter(|(_, dst)| *dst == offset) {
+            let _ = writeln!(out, "{}:", name);
+        }
+    };
+
+    let mut out = String::new();
+    for inst in &instructions {
+        write_labels(&mut out, inst.offset);
+
+        let args: Vec<String> = inst
+            .args
+            .iter()
+            .map(|arg| match *arg {
+                Arg::Register(reg) => format!("r{}", reg),
+                Arg::Int(int) => int.to_string(),
+                Arg::Target(dst) => label_name(dst),
+            })
+            .collect();
+
+        let line = format!("    {:<8}{}", inst.op.mnemonic(), args.join(", "));
+        let _ = writeln!(out, "{:<40}; {:04x}", line.trim_end(), inst.offset);
+    }
+    write_labels(&mut out, program.instructions.len());
+
+    Ok(out)
+}
+
+fn bytecode_error(message: impl Into<String>) -> Error {
+    Error::new(CodeLocation::new(0, 0), message)
+}
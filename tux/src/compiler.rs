@@ -2,11 +2,13 @@
 
 use std::collections::HashMap;
 
+use crate::bytecode::Program;
 use crate::parser::{IRData, Value, IR};
 use crate::utils::*;
 
 pub type Instructions = Vec<u8>;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Op {
     NoOp = 0,
     Move = 1,
@@ -71,10 +73,70 @@ impl TryFrom<u8> for Op {
     }
 }
 
-pub fn compile(ir: Vec<IR>) -> Result<Instructions> {
+/// The kind of an operand as it is laid out in the bytecode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    /// A single byte register index.
+    Register,
+    /// A tag byte followed by either a register index (tag `1`) or a 2 byte integer (tag `0`).
+    Value,
+    /// A 2 byte jump offset relative to the start of the operand.
+    Jump,
+}
+
+impl Op {
+    pub fn mnemonic(self) -> &'static str {
+        use Op::*;
+        match self {
+            NoOp => "noop",
+            Move => "move",
+            Store => "store",
+            Add => "add",
+            Subtract => "sub",
+            Multiply => "mul",
+            Divide => "div",
+            Stbg => "stbg",
+            Stps => "stps",
+            Stcl => "stcl",
+            Strd => "strd",
+            Cmp => "cmp",
+            Jmp => "jmp",
+            Jeq => "jeq",
+            Jne => "jne",
+            Jlt => "jlt",
+            Jgt => "jgt",
+            Jle => "jle",
+            Jge => "jge",
+            Rect => "rect",
+            Line => "line",
+            Elps => "elps",
+            Vert => "vert",
+            Pgon => "pgon",
+        }
+    }
+
+    pub fn operands(self) -> &'static [Operand] {
+        use Op::*;
+        use Operand::*;
+        match self {
+            NoOp | Pgon => &[],
+            Store => &[Register, Value],
+            Add | Subtract | Multiply | Divide => &[Register, Value, Value],
+            Stbg | Stcl => &[Value, Value, Value],
+            Strd => &[Value],
+            Move | Stps | Cmp | Rect | Line | Elps | Vert => &[Value, Value],
+            Jmp | Jeq | Jne | Jlt | Jgt | Jle | Jge => &[Jump],
+        }
+    }
+}
+
+pub fn compile(ir: Vec<IR>) -> Result<Program> {
     let mut c = Compiler::new();
 
     for i in ir {
+        let offset = c.instructions.len();
+        let location = i.location;
+
         use IRData::*;
         match i.data {
             DefineLabel(label) => {
@@ -165,25 +227,25 @@ pub fn compile(ir: Vec<IR>) -> Result<Instructions> {
                 c.emit_value(b);
             }
             Jmp(label) => {
-                c.emit_jump(Op::Jmp, label);
+                c.emit_jump(Op::Jmp, label, i.location);
             }
             Jeq(label) => {
-                c.emit_jump(Op::Jeq, label);
+                c.emit_jump(Op::Jeq, label, i.location);
             }
             Jne(label) => {
-                c.emit_jump(Op::Jne, label);
+                c.emit_jump(Op::Jne, label, i.location);
             }
             Jlt(label) => {
-                c.emit_jump(Op::Jlt, label);
+                c.emit_jump(Op::Jlt, label, i.location);
             }
             Jgt(label) => {
-                c.emit_jump(Op::Jgt, label);
+                c.emit_jump(Op::Jgt, label, i.location);
             }
             Jle(label) => {
-                c.emit_jump(Op::Jle, label);
+                c.emit_jump(Op::Jle, label, i.location);
             }
             Jge(label) => {
-                c.emit_jump(Op::Jge, label);
+                c.emit_jump(Op::Jge, label, i.location);
             }
             Rect(w, h) => {
                 c.emit_op(Op::Rect);
@@ -209,14 +271,33 @@ pub fn compile(ir: Vec<IR>) -> Result<Instructions> {
                 c.emit_op(Op::Pgon);
             }
         }
+
+        if c.instructions.len() > offset {
+            c.locations.push((offset, location));
+        }
+    }
+
+    if let Some(uj) = c.unresolved_jumps.first() {
+        return Err(Error::new(
+            uj.location,
+            format!("Jump to undefined label `{}`.", uj.label),
+        ));
     }
 
-    Ok(c.instructions)
+    let mut labels: Vec<(String, usize)> = c.labels.into_iter().collect();
+    labels.sort_by(|(a_name, a_dst), (b_name, b_dst)| a_dst.cmp(b_dst).then(a_name.cmp(b_name)));
+
+    Ok(Program {
+        labels,
+        locations: c.locations,
+        instructions: c.instructions,
+    })
 }
 
 struct Compiler {
     labels: HashMap<String, usize>,
     unresolved_jumps: Vec<UnresolvedJump>,
+    locations: Vec<(usize, CodeLocation)>,
     instructions: Instructions,
 }
 
@@ -225,6 +306,7 @@ impl Compiler {
         Self {
             labels: Default::default(),
             unresolved_jumps: Default::default(),
+            locations: Default::default(),
             instructions: Default::default(),
         }
     }
@@ -257,7 +339,7 @@ impl Compiler {
         }
     }
 
-    fn emit_jump(&mut self, jump_op: Op, label: String) {
+    fn emit_jump(&mut self, jump_op: Op, label: String, location: CodeLocation) {
         self.emit_op(jump_op);
         if let Some(&dst) = self.labels.get(&label) {
             let jump = (dst.wrapping_sub(self.instructions.len())) as i16;
@@ -267,6 +349,7 @@ impl Compiler {
             let unresolved = UnresolvedJump {
                 label,
                 instructions_index: jmp_dst_idx,
+                location,
             };
             self.unresolved_jumps.push(unresolved);
             self.emit_int(0xBAAD);
@@ -277,4 +360,5 @@ impl Compiler {
 struct UnresolvedJump {
     label: String,
     instructions_index: usize,
+    location: CodeLocation,
 }
@@ -1,3 +1,4 @@
+use crate::bytecode::Program;
 use crate::compiler;
 use crate::utils::*;
 
@@ -5,7 +6,12 @@ use piston_window::*;
 
 pub const NUM_REGISTERS: usize = 16;
 
-pub fn evaluate(program_name: String, inst: compiler::Instructions) -> Result<()> {
+pub const DEFAULT_MAX_STEPS: usize = 1_000_000;
+
+pub fn evaluate(program_name: String, program: Program, max_steps: usize) -> Result<()> {
+    let mut vm = VM::new();
+    execute(&mut vm, &program, max_steps)?;
+
     let mut window: PistonWindow = WindowSettings::new(program_name, (640, 480))
         .exit_on_esc(true)
         .build()
@@ -16,191 +22,7 @@ pub fn evaluate(program_name: String, inst: compiler::Instructions) -> Result<()
 
     window.events.set_event_settings(event_loop_settings);
 
-    let mut vm = VM::new();
-
     while let Some(e) = window.next() {
-        while vm.ip < inst.len() {
-            let op: compiler::Op = inst[vm.ip].try_into().expect("Expected valid `Op`.");
-
-            vm.ip += 1;
-
-            use compiler::Op::*;
-            match op {
-                NoOp => panic!("NoOp encountered."),
-                Move => {
-                    let x = extract_value(&mut vm, &inst);
-                    let y = extract_value(&mut vm, &inst);
-
-                    vm.pen_position.x += x;
-                    vm.pen_position.y += y;
-                }
-                Store => {
-                    let reg = inst[vm.ip];
-                    vm.ip += 1;
-
-                    let new_value = extract_value(&mut vm, &inst);
-
-                    vm.registers[reg as usize] = new_value;
-                }
-                Add => {
-                    let reg = inst[vm.ip];
-                    vm.ip += 1;
-
-                    let a = extract_value(&mut vm, &inst);
-                    let b = extract_value(&mut vm, &inst);
-
-                    vm.registers[reg as usize] = a + b;
-                }
-                Subtract => {
-                    let reg = inst[vm.ip];
-                    vm.ip += 1;
-
-                    let a = extract_value(&mut vm, &inst);
-                    let b = extract_value(&mut vm, &inst);
-
-                    vm.registers[reg as usize] = a - b;
-                }
-                Multiply => {
-                    let reg = inst[vm.ip];
-                    vm.ip += 1;
-
-                    let a = extract_value(&mut vm, &inst);
-                    let b = extract_value(&mut vm, &inst);
-
-                    vm.registers[reg as usize] = a * b;
-                }
-                Divide => {
-                    let reg = inst[vm.ip];
-                    vm.ip += 1;
-
-                    let a = extract_value(&mut vm, &inst);
-                    let b = extract_value(&mut vm, &inst);
-
-                    vm.registers[reg as usize] = a / b;
-                }
-                Stbg => {
-                    vm.background_color[0] = extract_value(&mut vm, &inst);
-                    vm.background_color[1] = extract_value(&mut vm, &inst);
-                    vm.background_color[2] = extract_value(&mut vm, &inst);
-                }
-                Stps => {
-                    vm.pen_position.x = extract_value(&mut vm, &inst);
-                    vm.pen_position.y = extract_value(&mut vm, &inst);
-                }
-                Stcl => {
-                    vm.pen_color[0] = extract_value(&mut vm, &inst);
-                    vm.pen_color[1] = extract_value(&mut vm, &inst);
-                    vm.pen_color[2] = extract_value(&mut vm, &inst);
-                }
-                Strd => {
-                    vm.pen_radius = extract_value(&mut vm, &inst);
-                }
-                Cmp => {
-                    let a = extract_value(&mut vm, &inst);
-                    let b = extract_value(&mut vm, &inst);
-                    vm.rc = match a.cmp(&b) {
-                        std::cmp::Ordering::Less => -1,
-                        std::cmp::Ordering::Equal => 0,
-                        std::cmp::Ordering::Greater => 1,
-                    };
-                }
-                Jmp => {
-                    let jump = extract_integer(&mut vm, &inst);
-                    vm.ip = vm.ip.wrapping_add((jump - 2) as usize); // -2 because the operand take 2 bytes
-                }
-                Jeq => {
-                    let jump = extract_integer(&mut vm, &inst);
-                    if vm.rc == 0 {
-                        vm.ip = vm.ip.wrapping_add((jump - 2) as usize); // -2 because the operand take 2 bytes
-                    }
-                }
-                Jne => {
-                    let jump = extract_integer(&mut vm, &inst);
-                    if vm.rc != 0 {
-                        vm.ip = vm.ip.wrapping_add((jump - 2) as usize); // -2 because the operand take 2 bytes
-                    }
-                }
-                Jlt => {
-                    let jump = extract_integer(&mut vm, &inst);
-                    if vm.rc == -1 {
-                        vm.ip = vm.ip.wrapping_add((jump - 2) as usize); // -2 because the operand take 2 bytes
-                    }
-                }
-                Jgt => {
-                    let jump = extract_integer(&mut vm, &inst);
-                    if vm.rc == 1 {
-                        vm.ip = vm.ip.wrapping_add((jump - 2) as usize); // -2 because the operand take 2 bytes
-                    }
-                }
-                Jle => {
-                    let jump = extract_integer(&mut vm, &inst);
-                    if vm.rc <= 0 {
-                        vm.ip = vm.ip.wrapping_add((jump - 2) as usize); // -2 because the operand take 2 bytes
-                    }
-                }
-                Jge => {
-                    let jump = extract_integer(&mut vm, &inst);
-                    if vm.rc >= 0 {
-                        vm.ip = vm.ip.wrapping_add((jump - 2) as usize); // -2 because the operand take 2 bytes
-                    }
-                }
-                Rect => {
-                    let w = extract_value(&mut vm, &inst);
-                    let h = extract_value(&mut vm, &inst);
-
-                    let rect = TuxShape::Rect {
-                        color: vm.pen_color,
-                        origin: vm.pen_position,
-                        width: w,
-                        height: h,
-                    };
-
-                    vm.shapes.push(rect);
-                }
-                Line => {
-                    let dx = extract_value(&mut vm, &inst);
-                    let dy = extract_value(&mut vm, &inst);
-
-                    let line = TuxShape::Line {
-                        color: vm.pen_color,
-                        origin: vm.pen_position,
-                        dx,
-                        dy,
-                    };
-
-                    vm.shapes.push(line);
-                }
-                Elps => {
-                    let w = extract_value(&mut vm, &inst);
-                    let h = extract_value(&mut vm, &inst);
-
-                    let ellipse = TuxShape::Ellipse {
-                        color: vm.pen_color,
-                        origin: vm.pen_position,
-                        width: w,
-                        height: h,
-                    };
-
-                    vm.shapes.push(ellipse);
-                }
-                Vert => {
-                    let x = extract_value(&mut vm, &inst);
-                    let y = extract_value(&mut vm, &inst);
-
-                    let vert = Position { x, y };
-
-                    vm.vertices.push(vert);
-                }
-                Pgon => {
-                    let pgon = TuxShape::Polygon {
-                        color: vm.pen_color,
-                        vertices: vm.vertices.drain(..).collect(),
-                    };
-                    vm.shapes.push(pgon);
-                }
-            }
-        }
-
         let shapes = vm.shapes.iter();
 
         window.draw_2d(&e, move |ctx, g, _| {
@@ -229,7 +51,10 @@ pub fn evaluate(program_name: String, inst: compiler::Instructions) -> Result<()
                             1.0,
                         ],
                         [origin.x as f64, origin.y as f64],
-                        [(origin.x + width) as f64, (origin.y + height) as f64],
+                        [
+                            origin.x as f64 + *width as f64,
+                            origin.y as f64 + *height as f64,
+                        ],
                         ctx.transform,
                         g,
                     ),
@@ -249,8 +74,8 @@ pub fn evaluate(program_name: String, inst: compiler::Instructions) -> Result<()
                         [
                             origin.x as f64,
                             origin.y as f64,
-                            (origin.x + dx) as f64,
-                            (origin.y + dy) as f64,
+                            origin.x as f64 + *dx as f64,
+                            origin.y as f64 + *dy as f64,
                         ],
                         ctx.transform,
                         g,
@@ -268,7 +93,10 @@ pub fn evaluate(program_name: String, inst: compiler::Instructions) -> Result<()
                             1.0,
                         ],
                         [origin.x as f64, origin.y as f64],
-                        [(origin.x + width) as f64, (origin.y + height) as f64],
+                        [
+                            origin.x as f64 + *width as f64,
+                            origin.y as f64 + *height as f64,
+                        ],
                         ctx.transform,
                         g,
                     ),
@@ -295,6 +123,248 @@ pub fn evaluate(program_name: String, inst: compiler::Instructions) -> Result<()
     Ok(())
 }
 
+/// Runs `program` to completion, collecting the shapes it draws in `vm`.
+/// A `max_steps` of 0 means there is no limit on executed instructions.
+fn execute(vm: &mut VM, program: &Program, max_steps: usize) -> Result<()> {
+    let inst = &program.instructions;
+
+    let mut steps = 0;
+    while vm.ip < inst.len() {
+        let location = program.location_of(vm.ip);
+
+        if max_steps != 0 && steps == max_steps {
+            return Err(Error::new(
+                location,
+                format!(
+                    "Exceeded the limit of {} executed instructions. Is there an infinite loop?",
+                    max_steps
+                ),
+            ));
+        }
+        steps += 1;
+
+        let op: compiler::Op = inst[vm.ip]
+            .try_into()
+            .map_err(|err: String| Error::new(location, err))?;
+
+        vm.ip += 1;
+
+        use compiler::Op::*;
+        match op {
+            NoOp => {
+                return Err(Error::new(location, "NoOp encountered."));
+            }
+            Move => {
+                let x = extract_value(vm, inst);
+                let y = extract_value(vm, inst);
+
+                let (px, py) = (vm.pen_position.x, vm.pen_position.y);
+                vm.pen_position.x = px
+                    .checked_add(x)
+                    .ok_or_else(|| overflow(location, "move", px, x))?;
+                vm.pen_position.y = py
+                    .checked_add(y)
+                    .ok_or_else(|| overflow(location, "move", py, y))?;
+            }
+            Store => {
+                let reg = inst[vm.ip];
+                vm.ip += 1;
+
+                let new_value = extract_value(vm, inst);
+
+                vm.registers[reg as usize] = new_value;
+            }
+            Add => {
+                let reg = inst[vm.ip];
+                vm.ip += 1;
+
+                let a = extract_value(vm, inst);
+                let b = extract_value(vm, inst);
+
+                vm.registers[reg as usize] = a
+                    .checked_add(b)
+                    .ok_or_else(|| overflow(location, "add", a, b))?;
+            }
+            Subtract => {
+                let reg = inst[vm.ip];
+                vm.ip += 1;
+
+                let a = extract_value(vm, inst);
+                let b = extract_value(vm, inst);
+
+                vm.registers[reg as usize] = a
+                    .checked_sub(b)
+                    .ok_or_else(|| overflow(location, "sub", a, b))?;
+            }
+            Multiply => {
+                let reg = inst[vm.ip];
+                vm.ip += 1;
+
+                let a = extract_value(vm, inst);
+                let b = extract_value(vm, inst);
+
+                vm.registers[reg as usize] = a
+                    .checked_mul(b)
+                    .ok_or_else(|| overflow(location, "mul", a, b))?;
+            }
+            Divide => {
+                let reg = inst[vm.ip];
+                vm.ip += 1;
+
+                let a = extract_value(vm, inst);
+                let b = extract_value(vm, inst);
+
+                if b == 0 {
+                    return Err(Error::new(
+                        location,
+                        format!("Division by zero (`div` of {} by 0).", a),
+                    ));
+                }
+
+                vm.registers[reg as usize] = a
+                    .checked_div(b)
+                    .ok_or_else(|| overflow(location, "div", a, b))?;
+            }
+            Stbg => {
+                vm.background_color[0] = extract_value(vm, inst);
+                vm.background_color[1] = extract_value(vm, inst);
+                vm.background_color[2] = extract_value(vm, inst);
+            }
+            Stps => {
+                vm.pen_position.x = extract_value(vm, inst);
+                vm.pen_position.y = extract_value(vm, inst);
+            }
+            Stcl => {
+                vm.pen_color[0] = extract_value(vm, inst);
+                vm.pen_color[1] = extract_value(vm, inst);
+                vm.pen_color[2] = extract_value(vm, inst);
+            }
+            Strd => {
+                vm.pen_radius = extract_value(vm, inst);
+            }
+            Cmp => {
+                let a = extract_value(vm, inst);
+                let b = extract_value(vm, inst);
+                vm.rc = match a.cmp(&b) {
+                    std::cmp::Ordering::Less => -1,
+                    std::cmp::Ordering::Equal => 0,
+                    std::cmp::Ordering::Greater => 1,
+                };
+            }
+            Jmp => {
+                let jump = extract_integer(vm, inst);
+                vm.ip = vm.ip.wrapping_add((jump - 2) as usize); // -2 because the operand take 2 bytes
+            }
+            Jeq => {
+                let jump = extract_integer(vm, inst);
+                if vm.rc == 0 {
+                    vm.ip = vm.ip.wrapping_add((jump - 2) as usize); // -2 because the operand take 2 bytes
+                }
+            }
+            Jne => {
+                let jump = extract_integer(vm, inst);
+                if vm.rc != 0 {
+                    vm.ip = vm.ip.wrapping_add((jump - 2) as usize); // -2 because the operand take 2 bytes
+                }
+            }
+            Jlt => {
+                let jump = extract_integer(vm, inst);
+                if vm.rc == -1 {
+                    vm.ip = vm.ip.wrapping_add((jump - 2) as usize); // -2 because the operand take 2 bytes
+                }
+            }
+            Jgt => {
+                let jump = extract_integer(vm, inst);
+                if vm.rc == 1 {
+                    vm.ip = vm.ip.wrapping_add((jump - 2) as usize); // -2 because the operand take 2 bytes
+                }
+            }
+            Jle => {
+                let jump = extract_integer(vm, inst);
+                if vm.rc <= 0 {
+                    vm.ip = vm.ip.wrapping_add((jump - 2) as usize); // -2 because the operand take 2 bytes
+                }
+            }
+            Jge => {
+                let jump = extract_integer(vm, inst);
+                if vm.rc >= 0 {
+                    vm.ip = vm.ip.wrapping_add((jump - 2) as usize); // -2 because the operand take 2 bytes
+                }
+            }
+            Rect => {
+                let w = extract_value(vm, inst);
+                let h = extract_value(vm, inst);
+
+                let rect = TuxShape::Rect {
+                    color: vm.pen_color,
+                    origin: vm.pen_position,
+                    width: w,
+                    height: h,
+                };
+
+                vm.shapes.push(rect);
+            }
+            Line => {
+                let dx = extract_value(vm, inst);
+                let dy = extract_value(vm, inst);
+
+                let line = TuxShape::Line {
+                    color: vm.pen_color,
+                    origin: vm.pen_position,
+                    dx,
+                    dy,
+                };
+
+                vm.shapes.push(line);
+            }
+            Elps => {
+                let w = extract_value(vm, inst);
+                let h = extract_value(vm, inst);
+
+                let ellipse = TuxShape::Ellipse {
+                    color: vm.pen_color,
+                    origin: vm.pen_position,
+                    width: w,
+                    height: h,
+                };
+
+                vm.shapes.push(ellipse);
+            }
+            Vert => {
+                let x = extract_value(vm, inst);
+                let y = extract_value(vm, inst);
+
+                let vert = Position { x, y };
+
+                vm.vertices.push(vert);
+            }
+            Pgon => {
+                let pgon = TuxShape::Polygon {
+                    color: vm.pen_color,
+                    vertices: vm.vertices.drain(..).collect(),
+                };
+                vm.shapes.push(pgon);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn overflow(location: CodeLocation, mnemonic: &str, a: i16, b: i16) -> Error {
+    Error::new(
+        location,
+        format!(
+            "Integer overflow in `{}` of {} and {}. Values must stay within {} and {}.",
+            mnemonic,
+            a,
+            b,
+            i16::MIN,
+            i16::MAX
+        ),
+    )
+}
+
 fn extract_integer(vm: &mut VM, inst: &compiler::Instructions) -> i16 {
     let byte1 = inst[vm.ip];
     vm.ip += 1;
@@ -2,92 +2,180 @@
 // use piston2d to do the image making
 //
 
+mod bytecode;
 mod compiler;
 mod evaluator;
 mod parser;
 mod utils;
 
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "\
+Usage:
+    tux <file> [--max-steps <n>]             Run a .tux source or .tuxc bytecode file.
+    tux run <file> [--max-steps <n>]         Same as above.
+    tux compile <file.tux> [-o <out.tuxc>]   Compile source to a bytecode file.
+    tux disasm <file>                        Print the mnemonics of a program.
+
+Options:
+    --max-steps <n>   Stop after executing <n> instructions, 0 for no limit (default 1000000).";
+
 fn main() -> Result<(), String> {
-    let mut args = std::env::args().skip(1);
-    let file_path = args
-        .next()
-        .ok_or("No file path given to tux interpreter.".to_string())?;
-    let file_path = std::path::Path::new(&file_path);
-
-    let source: &str = Box::leak(
-        std::fs::read_to_string(&file_path)
-            .or(Err(format!(
-                "Failed to read file {}",
-                file_path
-                    .file_name()
-                    .expect("Expected valid file path")
-                    .to_str()
-                    .expect("Expected file path to be valid UTF-8")
-            )))?
-            .into_boxed_str(),
-    );
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
 
-    let ir = match parser::parse(source) {
-        Ok(ir) => ir,
-        Err(err) => {
-            eprintln!(
-                "{}",
-                utils::DisplayableError::new(
-                    file_path
-                        .file_name()
-                        .expect("Expected valid file path")
-                        .to_str()
-                        .expect("Expected file path to be valid UTF-8")
-                        .to_string(),
-                    err
-                )
-            );
+    let command = match args.first().map(String::as_str) {
+        Some("run" | "compile" | "disasm") => args.remove(0),
+        Some("help" | "-h" | "--help") => {
+            println!("{}", USAGE);
             return Ok(());
         }
+        Some(_) => "run".to_string(),
+        None => {
+            return Err(format!(
+                "No file path given to tux interpreter.\n\n{}",
+                USAGE
+            ))
+        }
     };
 
-    let inst = match compiler::compile(ir) {
-        Ok(inst) => inst,
-        Err(err) => {
-            eprintln!(
-                "{}",
-                utils::DisplayableError::new(
-                    file_path
-                        .file_name()
-                        .expect("Expected valid file path")
-                        .to_str()
-                        .expect("Expected file path to be valid UTF-8")
-                        .to_string(),
-                    err
-                )
-            );
-            return Ok(());
+    let out_path = take_option(&mut args, "-o")?;
+    let max_steps = match take_option(&mut args, "--max-steps")? {
+        Some(n) => n
+            .parse()
+            .or(Err(format!("Invalid value `{}` for `--max-steps`.", n)))?,
+        None => evaluator::DEFAULT_MAX_STEPS,
+    };
+
+    let file_path = match args.as_slice() {
+        [file_path] => Path::new(file_path),
+        [] => {
+            return Err(format!(
+                "No file path given to `tux {}`.\n\n{}",
+                command, USAGE
+            ))
+        }
+        _ => {
+            return Err(format!(
+                "Unexpected arguments to `tux {}`.\n\n{}",
+                command, USAGE
+            ))
         }
     };
 
-    if let Err(err) = evaluator::evaluate(
-        file_path
-            .file_stem()
-            .expect("Expected a file stem")
-            .to_str()
-            .expect("Expected stem to be valid UTF-8")
-            .to_string(),
-        inst,
-    ) {
-        eprintln!(
-            "{}",
-            utils::DisplayableError::new(
+    let (program, source) = match load_program(file_path)? {
+        Some(loaded) => loaded,
+        None => return Ok(()),
+    };
+
+    match command.as_str() {
+        "compile" => {
+            let out_path = out_path
+                .map(PathBuf::from)
+                .unwrap_or_else(|| file_path.with_extension(bytecode::EXTENSION));
+
+            let bytes = match program.to_bytes() {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    report(file_path, source, err);
+                    return Ok(());
+                }
+            };
+
+            std::fs::write(&out_path, bytes)
+                .or(Err(format!("Failed to write file {}", out_path.display())))?;
+        }
+        "disasm" => match bytecode::disassemble(&program) {
+            Ok(text) => print!("{}", text),
+            Err(err) => report(file_path, source, err),
+        },
+        _ => {
+            if let Err(err) = evaluator::evaluate(
                 file_path
-                    .file_name()
-                    .expect("Expected valid file path")
+                    .file_stem()
+                    .expect("Expected a file stem")
                     .to_str()
-                    .expect("Expected file path to be valid UTF-8")
+                    .expect("Expected stem to be valid UTF-8")
                     .to_string(),
-                err
-            )
-        );
-        return Ok(());
+                program,
+                max_steps,
+            ) {
+                report(file_path, source, err);
+            }
+        }
     }
 
     Ok(())
 }
+
+/// Removes `name` and the value following it from `args`.
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    match args.iter().position(|arg| arg == name) {
+        Some(idx) if idx + 1 < args.len() => {
+            let value = args.remove(idx + 1);
+            args.remove(idx);
+            Ok(Some(value))
+        }
+        Some(_) => Err(format!("Expected a value after `{}`.", name)),
+        None => Ok(None),
+    }
+}
+
+/// Loads either a `.tuxc` bytecode file or compiles a tux source file. The
+/// result is verified before it is handed back along with the source, if
+/// there is any. Errors in the program itself are reported here and yield `None`.
+fn load_program(
+    file_path: &Path,
+) -> Result<Option<(bytecode::Program, Option<&'static str>)>, String> {
+    let read_error = || format!("Failed to read file {}", file_name(file_path));
+
+    let (program, source) =
+        if file_path.extension().and_then(|ext| ext.to_str()) == Some(bytecode::EXTENSION) {
+            let bytes = std::fs::read(file_path).or(Err(read_error()))?;
+            (bytecode::Program::from_bytes(&bytes), None)
+        } else {
+            let source: &str = Box::leak(
+                std::fs::read_to_string(file_path)
+                    .or(Err(read_error()))?
+                    .into_boxed_str(),
+            );
+            (
+                parser::parse(source, file_path).and_then(compiler::compile),
+                Some(source),
+            )
+        };
+
+    match program.and_then(|program| bytecode::verify(&program).map(|_| program)) {
+        Ok(program) => Ok(Some((program, source))),
+        Err(err) => {
+            report(file_path, source, err);
+            Ok(None)
+        }
+    }
+}
+
+fn report(file_path: &Path, source: Option<&str>, err: utils::Error) {
+    // Errors inside `%include`d files point at that file instead.
+    let included_source = err
+        .location
+        .file
+        .and_then(|file| std::fs::read_to_string(file).ok());
+    let source = match err.location.file {
+        Some(_) => included_source.as_deref(),
+        None => source,
+    };
+
+    let mut err = utils::DisplayableError::new(file_name(file_path), err);
+    if let Some(source) = source {
+        err = err.with_source(source);
+    }
+    eprintln!("{}", err);
+}
+
+fn file_name(file_path: &Path) -> String {
+    file_path
+        .file_name()
+        .expect("Expected valid file path")
+        .to_str()
+        .expect("Expected file path to be valid UTF-8")
+        .to_string()
+}
@@ -1,18 +1,25 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
 use crate::{
     evaluator,
     utils::{CodeLocation, Error, Result},
 };
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 struct Token {
     location: CodeLocation,
     data: TokenData,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 enum TokenData {
     Label(String),
 
+    // Preprocessor
+    Directive(String),
+    Str(String),
+
     // Values
     Int(i16),
     Reg(u8),
@@ -49,21 +56,27 @@ enum TokenData {
 
 struct Tokenizer {
     source: &'static str,
+    file: Option<&'static str>,
     index: usize,
     line: usize,
     col: usize,
 }
 
 impl Tokenizer {
-    fn new(source: &'static str) -> Self {
+    fn new(source: &'static str, file: Option<&'static str>) -> Self {
         Self {
             source,
+            file,
             index: 0,
             line: 1,
             col: 1,
         }
     }
 
+    fn location(&self, col: usize) -> CodeLocation {
+        CodeLocation::in_file(self.line, col, self.file)
+    }
+
     fn has_more(&self) -> bool {
         self.index < self.source.len()
     }
@@ -104,13 +117,13 @@ impl Tokenizer {
         let token: Token;
         if c == ',' {
             token = Token {
-                location: CodeLocation::new(self.line, self.col),
+                location: self.location(self.col),
                 data: TokenData::Comma,
             };
             self.advance();
         } else if c == ':' {
             token = Token {
-                location: CodeLocation::new(self.line, self.col),
+                location: self.location(self.col),
                 data: TokenData::Colon,
             };
             self.advance();
@@ -120,9 +133,24 @@ impl Tokenizer {
             token = self.tokenize_integer()?;
         } else if c.is_ascii_alphabetic() {
             token = self.tokenize_symbol();
+        } else if c == '%'
+            && self
+                .peek_char(1)
+                .filter(char::is_ascii_alphabetic)
+                .is_some()
+        {
+            let location = self.location(self.col);
+            self.advance();
+            let word = self.tokenize_word();
+            token = Token {
+                location,
+                data: TokenData::Directive(word),
+            };
+        } else if c == '"' {
+            token = self.tokenize_string()?;
         } else {
             return Err(Error::new(
-                CodeLocation::new(self.line, self.col),
+                self.location(self.col),
                 format!("What the frick is this!!! `{}`", c),
             ));
         }
@@ -161,23 +189,48 @@ impl Tokenizer {
         }
 
         let int: i16 = word.parse().or(Err(Error::new(
-            CodeLocation::new(self.line, c0),
+            self.location(c0),
             "Integer literal too large!",
         )))?;
 
         Ok(Token {
-            location: CodeLocation::new(self.line, c0),
+            location: self.location(c0),
             data: TokenData::Int(int),
         })
     }
 
-    fn tokenize_symbol(&mut self) -> Token {
-        let c0 = self.col;
+    fn tokenize_string(&mut self) -> Result<Token> {
+        let location = self.location(self.col);
+        self.advance();
+
+        let mut string = String::new();
+        loop {
+            match self.peek_char(0) {
+                Some('"') => {
+                    self.advance();
+                    break;
+                }
+                Some('\n') | None => {
+                    return Err(Error::new(location, "Unterminated string literal."));
+                }
+                Some(c) => {
+                    string.push(c);
+                    self.advance();
+                }
+            }
+        }
 
+        Ok(Token {
+            location,
+            data: TokenData::Str(string),
+        })
+    }
+
+    fn tokenize_word(&mut self) -> String {
         let mut word = String::new();
         while self
             .peek_char(0)
-            .filter(char::is_ascii_alphanumeric)
+            .filter(|&c| c.is_ascii_alphanumeric() || c == '_')
             .is_some()
             && self.has_more()
         {
@@ -187,6 +240,13 @@ impl Tokenizer {
             );
             self.advance();
         }
+        word
+    }
+
+    fn tokenize_symbol(&mut self) -> Token {
+        let c0 = self.col;
+
+        let word = self.tokenize_word();
 
         if word.len() > 1
             && word.starts_with('r')
@@ -198,7 +258,7 @@ impl Tokenizer {
             let reg_num = reg_num_str.parse().expect("Expected all ascii digits.");
 
             Token {
-                location: CodeLocation::new(self.line, c0),
+                location: self.location(c0),
                 data: TokenData::Reg(reg_num),
             }
         } else {
@@ -230,13 +290,410 @@ impl Tokenizer {
             };
 
             Token {
-                location: CodeLocation::new(self.line, c0),
+                location: self.location(c0),
                 data,
             }
         }
     }
 }
 
+const MAX_MACRO_DEPTH: usize = 64;
+
+struct Macro {
+    params: Vec<String>,
+    /// Labels defined inside the body, renamed on every expansion so a macro
+    /// can be used more than once. The new names contain `.`, which the
+    /// tokenizer never puts in a label, so they can't clash with user labels.
+    locals: HashSet<String>,
+    body: Vec<Token>,
+}
+
+/// Sits between the tokenizers and the parser, handling `%include` and
+/// `%macro` so the parser only ever sees plain instructions. Expanded tokens
+/// keep the location they were written at.
+struct TokenStream {
+    /// Stack of files being tokenized, the innermost `%include` last.
+    files: Vec<(Tokenizer, PathBuf)>,
+    included: HashSet<PathBuf>,
+    /// Tokens of expanded macros along with their expansion depth.
+    pending: VecDeque<(Token, usize)>,
+    macros: HashMap<String, Macro>,
+    /// Names of labels defined so far, which macros can't reuse.
+    labels: HashSet<String>,
+    expansions: usize,
+}
+
+impl TokenStream {
+    fn new(source: &'static str, path: &Path) -> Self {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        Self {
+            files: vec![(Tokenizer::new(source, None), path.clone())],
+            included: HashSet::from([path]),
+            pending: VecDeque::new(),
+            macros: HashMap::new(),
+            labels: HashSet::new(),
+            expansions: 0,
+        }
+    }
+
+    fn end_location(&self) -> CodeLocation {
+        let (t, _) = self.files.last().expect("Expected a file being tokenized.");
+        t.location(t.col)
+    }
+
+    /// The next token without any preprocessing. Doesn't leave the current file.
+    fn next_raw(&mut self) -> Result<Option<(Token, usize)>> {
+        if let Some(pending) = self.pending.pop_front() {
+            return Ok(Some(pending));
+        }
+
+        let (t, _) = self
+            .files
+            .last_mut()
+            .expect("Expected a file being tokenized.");
+        Ok(t.next()?.map(|token| (token, 0)))
+    }
+
+    /// Whether the next raw token is `data`, without consuming it.
+    fn peek_raw_is(&mut self, data: &TokenData) -> Result<bool> {
+        let next = self.next_raw()?;
+        let is = matches!(&next, Some((token, _)) if token.data == *data);
+        if let Some(next) = next {
+            self.pending.push_front(next);
+        }
+        Ok(is)
+    }
+
+    /// The next token where an instruction or label definition is expected.
+    /// Macros are only expanded here.
+    fn next(&mut self) -> Result<Option<Token>> {
+        self.next_token(true)
+    }
+
+    /// The next token where an operand is expected.
+    fn next_operand(&mut self) -> Result<Option<Token>> {
+        self.next_token(false)
+    }
+
+    fn next_token(&mut self, in_instruction: bool) -> Result<Option<Token>> {
+        loop {
+            let (token, depth) = match self.next_raw()? {
+                Some(next) => next,
+                None if self.files.len() > 1 => {
+                    self.files.pop();
+                    continue;
+                }
+                None => return Ok(None),
+            };
+
+            match &token.data {
+                TokenData::Directive(directive) => match directive.as_str() {
+                    "include" => self.include(token.location)?,
+                    "macro" => self.define_macro(token.location)?,
+                    "endmacro" => {
+                        return Err(Error::new(
+                            token.location,
+                            "`%endmacro` without a matching `%macro`.",
+                        ))
+                    }
+                    _ => {
+                        return Err(Error::new(
+                            token.location,
+                            format!(
+                                "Unknown directive `%{}`. Macro parameters can only be used inside a macro.",
+                                directive
+                            ),
+                        ))
+                    }
+                },
+                TokenData::Label(name) if in_instruction && self.macros.contains_key(name) => {
+                    let name = name.clone();
+                    if self.peek_raw_is(&TokenData::Colon)? {
+                        return Err(Error::new(
+                            token.location,
+                            format!("Label `{}` has the same name as a macro.", name),
+                        ));
+                    }
+                    self.expand(&name, token.location, depth)?;
+                }
+                TokenData::Label(name) if in_instruction => {
+                    self.labels.insert(name.clone());
+                    return Ok(Some(token));
+                }
+                _ => return Ok(Some(token)),
+            }
+        }
+    }
+
+    fn include(&mut self, location: CodeLocation) -> Result<()> {
+        let file_name = match self.next_raw()? {
+            Some((
+                Token {
+                    data: TokenData::Str(file_name),
+                    ..
+                },
+                _,
+            )) => file_name,
+            _ => {
+                return Err(Error::new(
+                    location,
+                    "Expected a file name in quotes after `%include`.",
+                ))
+            }
+        };
+
+        let (_, includer) = self.files.last().expect("Expected a file being tokenized.");
+        let path = includer
+            .parent()
+            .map(|dir| dir.join(&file_name))
+            .unwrap_or_else(|| PathBuf::from(&file_name));
+
+        let canonical = path.canonicalize().map_err(|_| {
+            Error::new(
+                location,
+                format!("Failed to find included file `{}`.", file_name),
+            )
+        })?;
+
+        // Every file is only included once, so libraries can include each other freely.
+        if !self.included.insert(canonical.clone()) {
+            return Ok(());
+        }
+
+        let source: &'static str = Box::leak(
+            std::fs::read_to_string(&canonical)
+                .map_err(|_| {
+                    Error::new(
+                        location,
+                        format!("Failed to read included file `{}`.", file_name),
+                    )
+                })?
+                .into_boxed_str(),
+        );
+        let display_name: &'static str =
+            Box::leak(path.to_string_lossy().into_owned().into_boxed_str());
+
+        self.files
+            .push((Tokenizer::new(source, Some(display_name)), canonical));
+
+        Ok(())
+    }
+
+    fn define_macro(&mut self, location: CodeLocation) -> Result<()> {
+        let (t, _) = self
+            .files
+            .last_mut()
+            .expect("Expected a file being tokenized.");
+
+        if !self.pending.is_empty() {
+            return Err(Error::new(
+                location,
+                "Macros can't be defined inside of other macros.",
+            ));
+        }
+
+        let name = match t.next()? {
+            Some(Token {
+                data: TokenData::Label(name),
+                location: name_location,
+            }) if name_location.line == location.line => name,
+            _ => {
+                return Err(Error::new(
+                    location,
+                    "Expected a macro name after `%macro`.",
+                ))
+            }
+        };
+
+        if self.macros.contains_key(&name) {
+            return Err(Error::new(
+                location,
+                format!("Redefinition of macro `{}`.", name),
+            ));
+        }
+
+        if self.labels.contains(&name) {
+            return Err(Error::new(
+                location,
+                format!("Macro `{}` has the same name as a label.", name),
+            ));
+        }
+
+        // Parameters are the names on the same line as `%macro`, separated by commas.
+        let mut params = vec![];
+        let mut commas = 0;
+        let mut next = t.next()?;
+        while let Some(token) = next.take() {
+            if token.location.line != location.line {
+                next = Some(token);
+                break;
+            }
+
+            match token.data {
+                TokenData::Label(param) if params.len() == commas => params.push(param),
+                TokenData::Comma if params.len() == commas + 1 => commas += 1,
+                _ => {
+                    return Err(Error::new(
+                        token.location,
+                        "Expected macro parameter names separated by commas.",
+                    ))
+                }
+            }
+
+            next = t.next()?;
+        }
+        if commas > 0 && params.len() == commas {
+            return Err(Error::new(
+                location,
+                "Expected a macro parameter name after `,`.",
+            ));
+        }
+
+        // Anything up to `%endmacro` is the body.
+        let mut body = vec![];
+        loop {
+            let token = match next {
+                Some(token) => token,
+                None => {
+                    return Err(Error::new(
+                        location,
+                        format!("Macro `{}` is missing its `%endmacro`.", name),
+                    ))
+                }
+            };
+
+            if let TokenData::Directive(directive) = &token.data {
+                match directive.as_str() {
+                    "endmacro" => break,
+                    "macro" | "include" => {
+                        return Err(Error::new(
+                            token.location,
+                            format!("`%{}` can't be used inside of a macro.", directive),
+                        ))
+                    }
+                    param if !params.iter().any(|p| p == param) => {
+                        return Err(Error::new(
+                            token.location,
+                            format!("Macro `{}` has no parameter `{}`.", name, param),
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+
+            body.push(token);
+            next = t.next()?;
+        }
+
+        let locals = body
+            .windows(2)
+            .filter_map(|pair| match (&pair[0].data, &pair[1].data) {
+                (TokenData::Label(label), TokenData::Colon) => Some(label.clone()),
+                _ => None,
+            })
+            .collect();
+
+        self.macros.insert(
+            name,
+            Macro {
+                params,
+                locals,
+                body,
+            },
+        );
+
+        Ok(())
+    }
+
+    fn expand(&mut self, name: &str, location: CodeLocation, depth: usize) -> Result<()> {
+        if depth >= MAX_MACRO_DEPTH {
+            return Err(Error::new(
+                location,
+                format!(
+                    "Macro `{}` expanded more than {} levels deep. Is it recursive?",
+                    name, MAX_MACRO_DEPTH
+                ),
+            ));
+        }
+
+        let param_count = self.macros[name].params.len();
+        let mut args = Vec::with_capacity(param_count);
+        for i in 0..param_count {
+            if i > 0 {
+                match self.next_raw()? {
+                    Some((
+                        Token {
+                            data: TokenData::Comma,
+                            ..
+                        },
+                        _,
+                    )) => {}
+                    _ => {
+                        return Err(Error::new(
+                            location,
+                            format!(
+                                "Macro `{}` expects {} arguments separated by commas.",
+                                name, param_count
+                            ),
+                        ))
+                    }
+                }
+            }
+
+            match self.next_raw()? {
+                Some((arg, _))
+                    if matches!(
+                        arg.data,
+                        TokenData::Int(_) | TokenData::Reg(_) | TokenData::Label(_)
+                    ) =>
+                {
+                    args.push(arg)
+                }
+                _ => {
+                    return Err(Error::new(
+                        location,
+                        format!(
+                            "Macro `{}` expects {} arguments, each an integer, register or label.",
+                            name, param_count
+                        ),
+                    ))
+                }
+            }
+        }
+
+        self.expansions += 1;
+        let m = &self.macros[name];
+        let expanded: Vec<(Token, usize)> = m
+            .body
+            .iter()
+            .map(|token| {
+                let token = match &token.data {
+                    TokenData::Directive(param) => {
+                        let idx = m
+                            .params
+                            .iter()
+                            .position(|p| p == param)
+                            .expect("Expected parameters to be checked in `define_macro`.");
+                        args[idx].clone()
+                    }
+                    TokenData::Label(label) if m.locals.contains(label) => Token {
+                        location: token.location,
+                        data: TokenData::Label(format!("{}.{}.{}", name, self.expansions, label)),
+                    },
+                    _ => token.clone(),
+                };
+                (token, depth + 1)
+            })
+            .collect();
+
+        for pending in expanded.into_iter().rev() {
+            self.pending.push_front(pending);
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct Value {
     pub from_reg: bool,
@@ -283,8 +740,8 @@ pub enum IRData {
     Pgon,
 }
 
-pub fn parse(source: &'static str) -> Result<Vec<IR>> {
-    let mut t = Tokenizer::new(source);
+pub fn parse(source: &'static str, path: &Path) -> Result<Vec<IR>> {
+    let mut t = TokenStream::new(source, path);
 
     let mut ir = vec![];
 
@@ -313,6 +770,18 @@ pub fn parse(source: &'static str) -> Result<Vec<IR>> {
                     "Didn't expect this random comma.",
                 ))
             }
+            Str(_) => {
+                return Err(Error::new(
+                    token.location,
+                    "Didn't expect this random string.",
+                ))
+            }
+            Directive(_) => {
+                return Err(Error::new(
+                    token.location,
+                    "Didn't expect this random directive.",
+                ))
+            }
             Colon => {
                 return Err(Error::new(
                     token.location,
@@ -488,11 +957,11 @@ pub fn parse(source: &'static str) -> Result<Vec<IR>> {
     Ok(ir)
 }
 
-fn eat(t: &mut Tokenizer, expected: TokenData, err: impl Into<String>) -> Result<()> {
-    let opt_token = t.next()?;
+fn eat(t: &mut TokenStream, expected: TokenData, err: impl Into<String>) -> Result<()> {
+    let opt_token = t.next_operand()?;
     let (data, location) = opt_token
         .map(|t| (Some(t.data), t.location))
-        .unwrap_or((None, CodeLocation::new(t.line, t.col)));
+        .unwrap_or((None, t.end_location()));
 
     data.filter(|d| *d == expected)
         .ok_or(Error::new(location, err.into()))?;
@@ -500,11 +969,11 @@ fn eat(t: &mut Tokenizer, expected: TokenData, err: impl Into<String>) -> Result
     Ok(())
 }
 
-fn parse_register_name(t: &mut Tokenizer) -> Result<u8> {
-    let opt_token = t.next()?;
+fn parse_register_name(t: &mut TokenStream) -> Result<u8> {
+    let opt_token = t.next_operand()?;
     let (data, location) = opt_token
         .map(|t| (Some(t.data), t.location))
-        .unwrap_or((None, CodeLocation::new(t.line, t.col)));
+        .unwrap_or((None, t.end_location()));
 
     let reg = data
         .map(|d| {
@@ -524,11 +993,11 @@ fn parse_register_name(t: &mut Tokenizer) -> Result<u8> {
     Ok(reg)
 }
 
-fn parse_value(t: &mut Tokenizer) -> Result<Value> {
-    let opt_token = t.next()?;
+fn parse_value(t: &mut TokenStream) -> Result<Value> {
+    let opt_token = t.next_operand()?;
     let (data, location) = opt_token
         .map(|t| (Some(t.data), t.location))
-        .unwrap_or((None, CodeLocation::new(t.line, t.col)));
+        .unwrap_or((None, t.end_location()));
 
     let value = data
         .map(|d| match d {
@@ -551,11 +1020,11 @@ fn parse_value(t: &mut Tokenizer) -> Result<Value> {
     Ok(value)
 }
 
-fn parse_label_name(t: &mut Tokenizer) -> Result<String> {
-    let opt_token = t.next()?;
+fn parse_label_name(t: &mut TokenStream) -> Result<String> {
+    let opt_token = t.next_operand()?;
     let (data, location) = opt_token
         .map(|t| (Some(t.data), t.location))
-        .unwrap_or((None, CodeLocation::new(t.line, t.col)));
+        .unwrap_or((None, t.end_location()));
 
     let label = data
         .map(|d| match d {
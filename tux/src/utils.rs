@@ -2,35 +2,81 @@
 pub struct CodeLocation {
     pub line: usize,
     pub col: usize,
+    /// Path of the `%include`d file this location is in, `None` for the file tux was run with.
+    pub file: Option<&'static str>,
 }
 
 impl CodeLocation {
     pub fn new(line: usize, col: usize) -> Self {
-        Self { line, col }
+        Self {
+            line,
+            col,
+            file: None,
+        }
+    }
+
+    pub fn in_file(line: usize, col: usize, file: Option<&'static str>) -> Self {
+        Self { line, col, file }
     }
 }
 
 pub struct DisplayableError {
     pub filename: String,
     pub err: Error,
+    pub source_line: Option<String>,
 }
 
 impl DisplayableError {
     pub fn new(filename: String, err: Error) -> Self {
-        Self { filename, err }
+        Self {
+            filename,
+            err,
+            source_line: None,
+        }
+    }
+
+    /// Attaches the source line the error points at so it is printed along with the error.
+    pub fn with_source(mut self, source: &str) -> Self {
+        if self.err.location.line != 0 {
+            self.source_line = source
+                .lines()
+                .nth(self.err.location.line - 1)
+                .map(str::to_string);
+        }
+        self
     }
 }
 
 impl std::fmt::Display for DisplayableError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let filename = self.err.location.file.unwrap_or(&self.filename);
         if self.err.location.line == 0 || self.err.location.col == 0 {
-            write!(f, "{}: Error: {}", self.filename, self.err.message)
+            write!(f, "{}: Error: {}", filename, self.err.message)
         } else {
             write!(
                 f,
                 "{}:{}:{}: Error: {}",
-                self.filename, self.err.location.line, self.err.location.col, self.err.message
-            )
+                filename, self.err.location.line, self.err.location.col, self.err.message
+            )?;
+
+            if let Some(line) = &self.source_line {
+                let gutter = self.err.location.line.to_string();
+                let padding: String = line
+                    .chars()
+                    .take(self.err.location.col - 1)
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                write!(
+                    f,
+                    "\n{} | {}\n{} | {}^",
+                    gutter,
+                    line,
+                    " ".repeat(gutter.len()),
+                    padding
+                )?;
+            }
+
+            Ok(())
         }
     }
 }